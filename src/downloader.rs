@@ -1,27 +1,66 @@
+pub(crate) mod cancel;
+#[cfg(feature = "verification")]
+pub(crate) mod checksum_file;
+pub(crate) mod collision;
 #[cfg(feature = "unarchive")]
 pub(crate) mod decompress;
+pub(crate) mod events;
+mod filename;
+#[cfg(feature = "verification")]
+pub(crate) mod integrity;
+mod probe;
+#[cfg(feature = "render_progress")]
+pub(crate) mod progress;
+pub(crate) mod report;
+mod resume;
+#[cfg(feature = "signatures")]
+pub(crate) mod signature;
 mod threads;
 #[cfg(feature = "verification")]
 pub(crate) mod verify;
 
 use crate::error::DownloadError;
+#[cfg(feature = "signatures")]
+use crate::error::SignatureError;
+use cancel::{CancelPolicy, DownloadHandle, Stop};
+#[cfg(feature = "verification")]
+use checksum_file::ChecksumFiles;
+use collision::{ensure_vacant, Claims, CollisionPolicy};
 #[cfg(feature = "unarchive")]
-use decompress::ArchiveFormat;
+use decompress::{ArchiveFormat, ExtractOptions};
+use events::{DownloadEvent, Events, ProgressObserver, RetryEvents};
 use futures::{
-    future,
+    future::{self, Either},
     stream::{self, StreamExt},
 };
-#[cfg(feature = "render_progress")]
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+#[cfg(feature = "verification")]
+use integrity::{ChecksumSource, ServerChecksums};
 use once_cell::sync::Lazy;
+use probe::Metadata;
+#[cfg(feature = "render_progress")]
+use progress::Progress;
+use report::{DownloadReport, DownloadSummary, ErrorPolicy};
 use reqwest::{header::HeaderMap, Url};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware};
 use reqwest_retry::{policies::ExponentialBackoff, RetryTransientMiddleware};
-use std::fs::File;
-use std::{path::PathBuf, sync::Arc, time::Duration};
+use resume::{ResumeState, Validators};
+#[cfg(feature = "signatures")]
+use signature::Signature;
+use std::fs::{File, OpenOptions};
+#[cfg(feature = "unarchive")]
+use std::io::Seek;
+use std::{
+    future::Future,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 const DEFAULT_RETRIES: u32 = 3;
 const DEFAULT_SIMULTANEOUS_DOWNLOADS: usize = 3;
+const DEFAULT_BUFFER_SIZE: usize = 1024 * 1024;
+const DEFAULT_PARTIAL_SUFFIX: &str = ".part";
+const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(6);
 
 static CURRENT_DIR: Lazy<PathBuf> = Lazy::new(|| std::env::current_dir().unwrap());
 
@@ -32,6 +71,23 @@ pub struct Downloader {
     progress: Option<Progress>,
     simultaneous: usize,
     retries: u32,
+    buffer_size: usize,
+    resume: bool,
+    partial_suffix: String,
+    error_policy: ErrorPolicy,
+    observers: Vec<Arc<dyn ProgressObserver>>,
+    handle: DownloadHandle,
+    cancel_policy: CancelPolicy,
+    collision_policy: CollisionPolicy,
+    #[cfg(feature = "verification")]
+    server_checksums: ServerChecksums,
+    #[cfg(feature = "verification")]
+    verify_existing: bool,
+    connect_timeout: Duration,
+    read_timeout: Option<Duration>,
+    timeout: Option<Duration>,
+    proxies: Vec<reqwest::Proxy>,
+    user_agent: Option<String>,
 }
 
 impl Downloader {
@@ -43,6 +99,23 @@ impl Downloader {
             progress: None,
             simultaneous: DEFAULT_SIMULTANEOUS_DOWNLOADS,
             retries: DEFAULT_RETRIES,
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            resume: false,
+            partial_suffix: DEFAULT_PARTIAL_SUFFIX.into(),
+            error_policy: ErrorPolicy::default(),
+            observers: Vec::new(),
+            handle: DownloadHandle::default(),
+            cancel_policy: CancelPolicy::default(),
+            collision_policy: CollisionPolicy::default(),
+            #[cfg(feature = "verification")]
+            server_checksums: ServerChecksums::default(),
+            #[cfg(feature = "verification")]
+            verify_existing: false,
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            read_timeout: None,
+            timeout: None,
+            proxies: Vec::new(),
+            user_agent: None,
         }
     }
     pub fn new_empty() -> Self {
@@ -53,6 +126,11 @@ impl Downloader {
         self.progress = Some(progress);
         self
     }
+    /// Register an observer which is notified of every [`DownloadEvent`]
+    pub fn with_observer(mut self, observer: impl ProgressObserver + 'static) -> Self {
+        self.observers.push(Arc::new(observer));
+        self
+    }
     pub fn with_download(mut self, download: Download) -> Self {
         self.downloads.push(download);
         self
@@ -65,107 +143,234 @@ impl Downloader {
         self.retries = retries;
         self
     }
-    pub async fn start_downloads(mut self) -> Result<(), DownloadError> {
-        let retries = ExponentialBackoff::builder().build_with_max_retries(self.retries);
-        let client = reqwest::ClientBuilder::new().connect_timeout(Duration::from_secs(6)).build()?;
-        let client = ClientBuilder::new(client)
-            .with(RetryTransientMiddleware::new_with_policy(retries))
-            .build();
+    /// Set the amount of data each chunk holds in memory before it is written to disk
+    pub fn with_buffer_size(mut self, buffer_size: usize) -> Self {
+        self.buffer_size = buffer_size.max(1);
+        self
+    }
+    /// Persist the progress of each download next to its output, so that an interrupted download
+    /// can pick up where it left off. If the remote file has changed since, the partial data is discarded and the download starts over.
+    pub fn with_resume(mut self, resume: bool) -> Self {
+        self.resume = resume;
+        self
+    }
+    /// Set the suffix appended to a file's name while it is being downloaded.
+    /// The file is only renamed to its final name once it has been downloaded, verified and decompressed.
+    pub fn with_partial_suffix(mut self, suffix: impl Into<String>) -> Self {
+        self.partial_suffix = suffix.into();
+        self
+    }
+    /// Choose whether the remaining downloads keep going once one of them has failed
+    pub fn with_error_policy(mut self, policy: ErrorPolicy) -> Self {
+        self.error_policy = policy;
+        self
+    }
+    /// Choose what happens to partially downloaded data when a download is cancelled through its [`DownloadHandle`]
+    pub fn with_cancel_policy(mut self, policy: CancelPolicy) -> Self {
+        self.cancel_policy = policy;
+        self
+    }
+    /// Choose what happens when a download's output file already exists. Downloads can override this individually.
+    pub fn with_collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collision_policy = policy;
+        self
+    }
+    /// Verify downloads which have no checksum of their own against digests sent by the server.
+    /// Downloads are only verified if the server sends a digest, so this can't detect a server which omits it.
+    #[cfg(feature = "verification")]
+    pub fn with_server_checksums(mut self, server_checksums: ServerChecksums) -> Self {
+        self.server_checksums = server_checksums;
+        self
+    }
+    /// Before contacting the server, check whether a download's output file is already present and matches its checksums,
+    /// in which case the download is skipped. This only applies to downloads with checksums given through
+    /// [`Download::with_checksum`] whose output path is known in advance: downloads which aren't extracted,
+    /// with a filename set or whose URL ends in one. Otherwise, the [`CollisionPolicy`] decides what happens to an existing file.
+    #[cfg(feature = "verification")]
+    pub fn with_verify_existing(mut self, verify_existing: bool) -> Self {
+        self.verify_existing = verify_existing;
+        self
+    }
+    /// Get a handle which can cancel or pause downloads while they are running
+    pub fn handle(&self) -> DownloadHandle {
+        self.handle.clone()
+    }
+    /// Use a preconfigured client for every request.
+    /// The client is used as-is: retries, timeouts, proxies and the user agent set on the `Downloader` are ignored,
+    /// and [`DownloadEvent::Retrying`] is never emitted.
+    pub fn with_client(mut self, client: ClientWithMiddleware) -> Self {
         self.client = Some(client);
-        self.fill_download_files().await?;
-        self.fill_lengths().await?;
-        self.finalize_threads();
+        self
+    }
+    pub fn with_connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+    /// Fail a request if no data is received for the given duration
+    pub fn with_read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        self
+    }
+    /// Limit the total time spent on each request, from connecting until the body has been received.
+    /// Each chunk of a multithreaded download is a separate request.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+    /// Send requests through a proxy. Multiple proxies can be added, the first one matching a URL is used.
+    pub fn with_proxy(mut self, proxy: reqwest::Proxy) -> Self {
+        self.proxies.push(proxy);
+        self
+    }
+    pub fn with_user_agent(mut self, user_agent: impl Into<String>) -> Self {
+        self.user_agent = Some(user_agent.into());
+        self
+    }
+    /// Run every download, returning the first error encountered
+    pub async fn start_downloads(self) -> Result<(), DownloadError> {
+        let mut errors = self
+            .start_downloads_with_report()
+            .await?
+            .into_iter()
+            .filter_map(|report| report.result.err());
+        // Downloads that were aborted only failed because of another download, so prefer reporting the original error
+        let first = errors.next();
+        match first {
+            Some(DownloadError::Aborted) => Err(errors
+                .find(|e| !matches!(e, DownloadError::Aborted))
+                .unwrap_or(DownloadError::Aborted)),
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+    /// Run every download, returning a report for each one in the order they were added
+    pub async fn start_downloads_with_report(mut self) -> Result<Vec<DownloadReport>, DownloadError> {
+        #[allow(unused_mut)]
+        let mut observers = std::mem::take(&mut self.observers);
         #[cfg(feature = "render_progress")]
-        let progress = self.initialize_progress();
+        let renderer = self
+            .progress
+            .as_ref()
+            .and_then(|progress| progress.renderer(self.downloads.len()))
+            .map(Arc::new);
         #[cfg(feature = "render_progress")]
-        let main = progress.and_then(|progress| progress.1);
+        if let Some(renderer) = &renderer {
+            observers.push(renderer.clone());
+        }
+        let events = Events::new(observers);
 
-        let downloads = self.downloads.into_iter().map(|download| {
-            download.spawn(
-                self.client.as_ref().unwrap(),
-                #[cfg(feature = "render_progress")]
-                main.clone(),
-            )
+        let client = match self.client.take() {
+            Some(client) => client,
+            None => self.build_client(&events)?,
+        };
+        let settings = Settings {
+            client,
+            events,
+            buffer_size: self.buffer_size,
+            resume: self.resume,
+            partial_suffix: self.partial_suffix,
+            handle: self.handle,
+            cancel_policy: self.cancel_policy,
+            collision_policy: self.collision_policy,
+            claims: Claims::default(),
+            #[cfg(feature = "verification")]
+            checksum_files: ChecksumFiles::default(),
+            #[cfg(feature = "verification")]
+            server_checksums: self.server_checksums,
+            #[cfg(feature = "verification")]
+            verify_existing: self.verify_existing,
+        };
+        let mut reports = self
+            .downloads
+            .iter()
+            .enumerate()
+            .map(|(id, download)| {
+                let url = (*download.url).clone();
+                settings.events.emit(DownloadEvent::Queued { id, url: url.clone() });
+                DownloadReport {
+                    url,
+                    result: Err(DownloadError::Aborted),
+                }
+            })
+            .collect::<Vec<_>>();
+        let downloads = self.downloads.into_iter().enumerate().map(|(id, download)| {
+            let settings = &settings;
+            async move { (id, download.run(id, settings).await) }
         });
-        stream::iter(downloads)
-            .buffer_unordered(self.simultaneous)
-            .collect::<Vec<_>>()
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>, DownloadError>>()?;
+        let mut results = stream::iter(downloads).buffer_unordered(self.simultaneous);
+        while let Some((id, result)) = results.next().await {
+            let event = match &result {
+                Ok(_) => DownloadEvent::Finished { id },
+                Err(error) => DownloadEvent::Failed { id, error: error.to_string() },
+            };
+            settings.events.emit(event);
+            if result.is_err() && self.error_policy == ErrorPolicy::FailFast {
+                // The remaining downloads still run to completion, so that they can clean up after themselves
+                settings.handle.stop_all(Stop::Abort);
+            }
+            reports[id].result = result;
+        }
 
         #[cfg(feature = "render_progress")]
-        if let Some(main_bar) = main {
-            main_bar.finish();
+        if let Some(renderer) = renderer {
+            renderer.finish();
         }
-        Ok(())
-    }
-    async fn fill_download_files(&mut self) -> Result<(), DownloadError> {
-        let futures = self.downloads.iter_mut().map(|download| download.fill_output());
-        future::join_all(futures)
-            .await
-            .into_iter()
-            .collect::<Result<Vec<_>, DownloadError>>()?;
-        Ok(())
+        Ok(reports)
     }
-    #[cfg(feature = "render_progress")]
-    fn initialize_progress(&mut self) -> Option<(MultiProgress, Option<ProgressBar>)> {
-        let progress = self.progress.as_ref()?;
-        if !progress.is_enabled() {
-            return None;
-        }
-        let multi = MultiProgress::new();
-        let main_bar = match (&progress.total, self.downloads.len()) {
-            (Some(style), 2..) => {
-                let progress = ProgressBar::new(self.downloads.len() as u64).with_style(style.clone());
-                progress.enable_steady_tick(std::time::Duration::from_millis(100));
-                Some(multi.add(progress))
-            }
-            _ => None,
-        };
-        if let Some(individual_style) = &progress.individual {
-            self.downloads.iter_mut().for_each(|download| {
-                let progress = ProgressBar::new(download.content_length.unwrap()).with_style(individual_style.clone());
-                progress.enable_steady_tick(std::time::Duration::from_millis(100));
-                download.progress = Some(multi.add(progress));
-            });
+    fn build_client(&mut self, events: &Events) -> Result<ClientWithMiddleware, DownloadError> {
+        let mut client = reqwest::ClientBuilder::new().connect_timeout(self.connect_timeout);
+        if let Some(timeout) = self.read_timeout {
+            client = client.read_timeout(timeout);
+        }
+        if let Some(timeout) = self.timeout {
+            client = client.timeout(timeout);
+        }
+        for proxy in self.proxies.drain(..) {
+            client = client.proxy(proxy);
+        }
+        if let Some(user_agent) = self.user_agent.take() {
+            client = client.user_agent(user_agent);
         }
-        Some((multi, main_bar))
+        let retries = ExponentialBackoff::builder().build_with_max_retries(self.retries);
+        Ok(ClientBuilder::new(client.build()?)
+            .with(RetryTransientMiddleware::new_with_policy(retries))
+            .with(RetryEvents(events.clone()))
+            .build())
     }
-    fn finalize_threads(&mut self) {
-        self.downloads.iter_mut().for_each(|download| {
-            if download.preferred_threads.is_none() {
-                download.preferred_threads = choose_threads(download.content_length, &download.url);
+}
+
+/// Options shared by every download in a single run
+struct Settings {
+    client: ClientWithMiddleware,
+    buffer_size: usize,
+    resume: bool,
+    partial_suffix: String,
+    events: Events,
+    handle: DownloadHandle,
+    cancel_policy: CancelPolicy,
+    collision_policy: CollisionPolicy,
+    claims: Claims,
+    #[cfg(feature = "verification")]
+    checksum_files: ChecksumFiles,
+    #[cfg(feature = "verification")]
+    server_checksums: ServerChecksums,
+    #[cfg(feature = "verification")]
+    verify_existing: bool,
+}
+
+impl Settings {
+    /// Drive a download until it completes, or until it is stopped through the [`DownloadHandle`]
+    async fn until_stopped<T>(&self, id: usize, future: impl Future<Output = Result<T, DownloadError>>) -> (Result<T, DownloadError>, Option<Stop>) {
+        let token = self.handle.token(id);
+        let cancelled = token.cancelled();
+        futures::pin_mut!(future, cancelled);
+        match future::select(future, cancelled).await {
+            Either::Left((result, _)) => (result, None),
+            Either::Right(_) => {
+                let stop = self.handle.reason(id);
+                (Err(stopped(stop)), stop)
             }
-        });
-    }
-    async fn fill_lengths(&mut self) -> Result<(), DownloadError> {
-        let client = self.client.as_ref().unwrap();
-        let futures = self
-            .downloads
-            .iter()
-            .map(|download| async {
-                let mut request = client.get((*download.url).clone());
-                if let Some(headers) = &download.headers {
-                    request = request.headers((**headers).clone());
-                }
-                request.send().await.map_err(DownloadError::RequestError)
-            })
-            .collect::<Vec<_>>();
-        let futures = future::join_all(futures).await;
-        self.downloads
-            .iter_mut()
-            .zip(futures)
-            .map(|(download, response)| {
-                let response = response?;
-                let length = response.content_length().ok_or(DownloadError::ContentLength)?;
-                let url = response.url().clone();
-                download.content_length = Some(length);
-                download.url = Arc::new(url);
-                Ok(())
-            })
-            .collect::<Result<Vec<_>, DownloadError>>()?;
-        Ok(())
+        }
     }
 }
 
@@ -174,7 +379,7 @@ const SINGLETHREADED_URLS: [&str; 2] = ["cdimage.ubuntu.com", "dl.sourceforge.ne
 fn choose_threads(length: Option<u64>, url: &Url) -> Option<u8> {
     if url
         .host_str()
-        .map_or(false, |host| SINGLETHREADED_URLS.iter().any(|&single| host.contains(single)))
+        .is_some_and(|host| SINGLETHREADED_URLS.iter().any(|&single| host.contains(single)))
     {
         return Some(1);
     }
@@ -189,17 +394,41 @@ fn choose_threads(length: Option<u64>, url: &Url) -> Option<u8> {
 pub struct Download {
     url: Arc<Url>,
     output: Option<File>,
+    path: Option<PathBuf>,
+    partial_path: Option<PathBuf>,
     directory: Option<PathBuf>,
     filename: Option<String>,
     headers: Option<Arc<HeaderMap>>,
     #[cfg(feature = "verification")]
-    checksum: Option<verify::Checksum>,
+    checksums: Vec<verify::Checksum>,
+    #[cfg(feature = "verification")]
+    checksum_url: Option<Url>,
+    #[cfg(feature = "verification")]
+    checksum_source: Option<ChecksumSource>,
+    #[cfg(feature = "signatures")]
+    signature: Option<Signature>,
     preferred_threads: Option<u8>,
     content_length: Option<u64>,
-    #[cfg(feature = "render_progress")]
-    progress: Option<ProgressBar>,
+    accepts_ranges: bool,
     #[cfg(feature = "unarchive")]
     decompress: Option<ArchiveFormat>,
+    #[cfg(feature = "unarchive")]
+    archive_path: Option<PathBuf>,
+    /// Where the archive is moved to once it has been extracted, if it's kept
+    #[cfg(feature = "unarchive")]
+    kept_archive: Option<PathBuf>,
+    #[cfg(feature = "unarchive")]
+    extract_options: ExtractOptions,
+    state_path: Option<PathBuf>,
+    resume_state: Option<ResumeState>,
+    collision_policy: Option<CollisionPolicy>,
+    /// Whether an existing file at the output path may be replaced
+    overwrite: bool,
+    /// Whether the output file already exists and shouldn't be downloaded again
+    skip: bool,
+    /// An existing output file which has already been found not to match the checksums
+    #[cfg(feature = "verification")]
+    invalid_existing: Option<PathBuf>,
 }
 
 impl Download {
@@ -211,19 +440,44 @@ impl Download {
         Self {
             url: url.into(),
             output: None,
+            path: None,
+            partial_path: None,
             directory: None,
             filename: None,
             headers: None,
             #[cfg(feature = "verification")]
-            checksum: None,
+            checksums: Vec::new(),
+            #[cfg(feature = "verification")]
+            checksum_url: None,
+            #[cfg(feature = "verification")]
+            checksum_source: None,
+            #[cfg(feature = "signatures")]
+            signature: None,
             preferred_threads: None,
             content_length: None,
-            #[cfg(feature = "render_progress")]
-            progress: None,
+            accepts_ranges: false,
             #[cfg(feature = "unarchive")]
             decompress: None,
+            #[cfg(feature = "unarchive")]
+            archive_path: None,
+            #[cfg(feature = "unarchive")]
+            kept_archive: None,
+            #[cfg(feature = "unarchive")]
+            extract_options: ExtractOptions::default(),
+            state_path: None,
+            resume_state: None,
+            collision_policy: None,
+            overwrite: false,
+            skip: false,
+            #[cfg(feature = "verification")]
+            invalid_existing: None,
         }
     }
+    /// Set the name of the output file. By default, the name is taken from the server's `Content-Disposition` header,
+    /// or otherwise from the last segment of the URL after following any redirects.
+    ///
+    /// Decompressed files are saved under this name as given. Tarballs, zip and 7z archives are extracted into a directory with this name
+    /// inside the output directory, which is created if it doesn't exist.
     pub fn with_filename(mut self, filename: String) -> Self {
         self.filename = Some(filename);
         self
@@ -232,6 +486,8 @@ impl Download {
         self.directory = Some(path);
         self
     }
+    /// Write to an already opened file. It must be readable if the download is verified against a checksum.
+    /// Data is written into it directly, so downloads into it are neither atomic nor resumable.
     pub fn with_output_file(mut self, file: impl Into<File>) -> Self {
         self.output = Some(file.into());
         self
@@ -240,130 +496,568 @@ impl Download {
         self.headers = Some(headers.into());
         self
     }
+    /// Verify the download against a checksum. Several checksums can be added, such as both a SHA-256 and a SHA-512 hash,
+    /// in which case all of them must match.
     #[cfg(feature = "verification")]
     pub fn with_checksum(mut self, checksum: verify::Checksum) -> Self {
-        self.checksum = Some(checksum);
+        self.checksums.push(checksum);
+        self.checksum_source = Some(ChecksumSource::Explicit);
+        self
+    }
+    /// Fetch a checksum file such as `SHA256SUMS` and verify the download against its entry for the remote file's name.
+    /// The file is only fetched once per run, however many downloads refer to it. A checksum given through
+    /// [`with_checksum`](Self::with_checksum) takes precedence.
+    #[cfg(feature = "verification")]
+    pub fn with_checksum_url(mut self, url: Url) -> Self {
+        self.checksum_url = Some(url);
+        self
+    }
+    /// Verify the download against a detached signature before it's saved.
+    /// A signature can cover the checksum file instead, see [`Signature::with_checksum_file`].
+    #[cfg(feature = "signatures")]
+    pub fn with_signature(mut self, signature: Signature) -> Self {
+        self.signature = Some(signature);
         self
     }
+    #[cfg(feature = "signatures")]
+    async fn fetch_signature(&mut self, id: usize, settings: &Settings) -> Result<(), DownloadError> {
+        let Some(signature) = &mut self.signature else {
+            return Ok(());
+        };
+        if signature.is_for_checksum_file() && self.checksum_url.is_none() {
+            return Err(SignatureError::MissingChecksumFile.into());
+        }
+        // An explicit checksum takes precedence over the checksum file, which would then never be checked against the signature
+        if signature.is_for_checksum_file() && !self.checksums.is_empty() {
+            return Err(SignatureError::UnusedChecksumFile.into());
+        }
+        settings
+            .until_stopped(id, signature.fetch(&settings.client, id, self.headers.as_deref()))
+            .await
+            .0
+    }
+    /// Look up the download's checksum in its checksum file, trying the name given by the server before the name in the requested URL.
+    /// Otherwise, fall back to a digest sent by the server if the [`ServerChecksums`] policy allows it.
+    #[cfg(feature = "verification")]
+    async fn fetch_checksum(&mut self, id: usize, metadata: &Metadata, settings: &Settings) -> Result<(), DownloadError> {
+        if !self.checksums.is_empty() {
+            return Ok(());
+        }
+        let Some(url) = &self.checksum_url else {
+            if let Some((checksum, source)) = integrity::from_headers(&metadata.headers, settings.server_checksums) {
+                log::debug!("Verifying {} against the {source:?} sent by the server", metadata.url);
+                self.checksums.push(checksum);
+                self.checksum_source = Some(source);
+            }
+            return Ok(());
+        };
+        let filenames = [filename::infer(&metadata.url, &metadata.headers), filename::infer(&self.url, &HeaderMap::new())];
+        let filenames: Vec<&str> = filenames.iter().flatten().map(String::as_str).collect();
+        let contents = settings
+            .until_stopped(
+                id,
+                settings
+                    .checksum_files
+                    .fetch(&settings.client, id, url, self.headers.as_deref()),
+            )
+            .await
+            .0?;
+        // The checksum file is only trusted once its signature has been checked
+        #[cfg(feature = "signatures")]
+        if let Some(signature) = self.signature.as_ref().filter(|signature| signature.is_for_checksum_file()) {
+            let verify = async { Ok(signature.verify(&contents[..], settings.buffer_size).await?) };
+            settings.until_stopped(id, verify).await.0?;
+        }
+        let (checksum, source) = checksum_file::find(&contents, url, &filenames)?;
+        self.checksums.push(checksum);
+        self.checksum_source = Some(source);
+        Ok(())
+    }
     pub fn with_threads(mut self, threads: u8) -> Self {
         self.preferred_threads = Some(threads);
         self
     }
+    /// Choose what happens if the output file already exists, overriding the policy set on the [`Downloader`]
+    pub fn with_collision_policy(mut self, policy: CollisionPolicy) -> Self {
+        self.collision_policy = Some(policy);
+        self
+    }
+    /// Decompress or unpack the download once it has finished. Use [`ArchiveFormat::Auto`] if the format isn't known in advance.
     #[cfg(feature = "unarchive")]
     pub fn with_archive_format(mut self, format: ArchiveFormat) -> Self {
         self.decompress = Some(format);
         self
     }
-    async fn fill_output(&mut self) -> Result<(), DownloadError> {
+    /// Choose which entries are extracted from the archive and where, and whether the archive itself is kept
+    #[cfg(feature = "unarchive")]
+    pub fn with_extract_options(mut self, options: ExtractOptions) -> Self {
+        self.extract_options = options;
+        self
+    }
+    /// Replace [`ArchiveFormat::Auto`] with the format of the remote file
+    #[cfg(feature = "unarchive")]
+    async fn detect_format(&mut self, id: usize, metadata: &Metadata, settings: &Settings) -> Result<(), DownloadError> {
+        let sniff = probe::sniff(
+            &settings.client,
+            id,
+            &metadata.url,
+            self.headers.as_deref(),
+            decompress::DETECTION_LENGTH,
+        );
+        let data = settings.until_stopped(id, sniff).await.0?;
+        let filename = self
+            .filename
+            .clone()
+            .or_else(|| filename::infer(&metadata.url, &metadata.headers));
+        let content_type = metadata
+            .headers
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        self.decompress = ArchiveFormat::identify(&data, filename.as_deref(), content_type);
+        match &self.decompress {
+            Some(format) => log::debug!("Detected {format:?} archive at {}", metadata.url),
+            None => log::warn!(
+                "Could not recognize the archive format of {}, saving it without extracting",
+                metadata.url
+            ),
+        }
+        Ok(())
+    }
+    fn fill_output(&mut self, metadata: &Metadata, settings: &Settings) -> Result<(), DownloadError> {
+        let dir = self.directory.clone().unwrap_or_else(|| CURRENT_DIR.clone());
+        let remote_filename = filename::infer(&metadata.url, &metadata.headers);
+        let url_filename = remote_filename.as_deref().unwrap_or("download");
+
         if self.output.is_none() {
             #[allow(unused_mut)]
-            let mut filename = self.filename.as_deref().unwrap_or_else(|| {
-                self.url
-                    .path_segments()
-                    .and_then(|segments| segments.last())
-                    .and_then(|name| if name.is_empty() { None } else { Some(name) })
-                    .unwrap_or("download")
-            });
+            let mut filename = Some(self.filename.as_deref().unwrap_or(url_filename));
             #[cfg(feature = "unarchive")]
             if let Some(archive_format) = &self.decompress {
-                if matches!(
-                    archive_format,
-                    ArchiveFormat::Zip | ArchiveFormat::Tar | ArchiveFormat::TarBz2 | ArchiveFormat::TarGz | ArchiveFormat::TarXz | ArchiveFormat::TarZst
-                ) && self.filename.is_some()
-                {
-                    return Err(DownloadError::UnsupportedFileName);
+                if archive_format.is_multi_file() {
+                    if let Some(filename) = &self.filename {
+                        self.directory = Some(dir.join(filename));
+                    }
+                    filename = None;
                 }
-                let archive_ext = match archive_format {
-                    ArchiveFormat::Bz2 => "bz2",
-                    ArchiveFormat::Gz => "gz",
-                    ArchiveFormat::Xz => "xz",
-                    ArchiveFormat::Zst => "zst",
-                    _ => "",
+                // Only names taken from the remote file carry the archive's extension
+                if let Some(archive_ext) = archive_format.extension().filter(|_| self.filename.is_none()) {
+                    filename = filename.map(|filename| {
+                        filename
+                            .strip_suffix(archive_ext)
+                            .and_then(|name| name.strip_suffix('.'))
+                            .unwrap_or(filename)
+                    });
+                }
+            }
+            self.path = filename
+                .map(|filename| dir.join(filename))
+                .map(|path| self.resolve_collision(path, settings))
+                .transpose()?;
+            if let Some(path) = &self.path {
+                self.partial_path = Some(claim_staging(path, &settings.partial_suffix, settings));
+            }
+        }
+        // Compressed data is staged on disk and removed once it has been unpacked
+        #[cfg(feature = "unarchive")]
+        if let Some(archive_format) = &self.decompress {
+            self.archive_path = Some(match (&self.path, archive_format.extension()) {
+                (Some(path), Some(archive_ext)) => claim_staging(path, &format!(".{archive_ext}{}", settings.partial_suffix), settings),
+                _ => claim_staging(&dir.join(url_filename), &settings.partial_suffix, settings),
+            });
+        }
+        #[cfg(feature = "unarchive")]
+        if let Some(archive_path) = self
+            .archive_path
+            .clone()
+            .filter(|_| self.extract_options.keep_archive() && !self.skip)
+        {
+            let name = archive_path.file_name().unwrap().to_string_lossy();
+            let kept_archive = archive_path.with_file_name(name.strip_suffix(settings.partial_suffix.as_str()).unwrap_or(&name));
+            self.kept_archive = Some(self.resolve_kept_archive(kept_archive, settings)?);
+        }
+
+        if let Some(target) = self.download_path().filter(|_| settings.resume).cloned() {
+            let state_path = with_suffix(&target, ".state");
+            self.state_path = Some(state_path.clone());
+            let state = match ResumeState::load(&state_path) {
+                Err(DownloadError::InvalidResumeState) => Err("its saved state can't be read"),
+                Ok(Some(state)) if !state.matches_url(&self.url) => Err("it was started from a different URL"),
+                Ok(Some(state)) if !state.matches_partial(&target) => Err("the partial file is missing data"),
+                result => Ok(result?),
+            };
+            match state {
+                Ok(state) => self.resume_state = state,
+                Err(reason) => self.discard_partial(reason)?,
+            }
+        }
+        Ok(())
+    }
+    /// Remove the partial data and state of a download which can't be resumed, so that it starts over instead of failing on every run
+    fn discard_partial(&mut self, reason: &str) -> Result<(), DownloadError> {
+        log::warn!("Not resuming {}: {reason}, starting over", self.url);
+        self.resume_state = None;
+        let paths: Vec<PathBuf> = self.download_path().into_iter().chain(&self.state_path).cloned().collect();
+        for path in paths {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(DownloadError::FileError(e)),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+    /// Apply the collision policy to the output path, returning the path the download should be saved to
+    fn resolve_collision(&mut self, path: PathBuf, settings: &Settings) -> Result<PathBuf, DownloadError> {
+        match self.collision_policy.unwrap_or(settings.collision_policy) {
+            CollisionPolicy::Fail => ensure_vacant(&path)?,
+            CollisionPolicy::Overwrite => self.overwrite = true,
+            CollisionPolicy::Skip => self.skip = path.exists(),
+            #[cfg(feature = "verification")]
+            CollisionPolicy::SkipIfValid => {
+                self.skip = match (self.checksums.as_slice(), path.is_file()) {
+                    ([], exists) => exists,
+                    (_, false) => false,
+                    _ if self.invalid_existing.as_ref() == Some(&path) => false,
+                    (checksums, true) => {
+                        let file = File::open(&path).map_err(DownloadError::FileError)?;
+                        verify::verify_reader(checksums, file, settings.buffer_size).map_err(DownloadError::FileError)?
+                    }
                 };
-                if filename.ends_with(archive_ext) {
-                    filename = &filename[..filename.len() - archive_ext.len() - 1];
+                self.overwrite = !self.skip;
+            }
+            CollisionPolicy::Rename => {
+                let path = (0..)
+                    .map(|number| collision::numbered(&path, number))
+                    .find(|path| !path.exists() && settings.claims.claim(path))
+                    .unwrap();
+                return Ok(path);
+            }
+        }
+        // Two downloads of the same run can't share an output path, regardless of the policy
+        if !self.skip && !settings.claims.claim(&path) {
+            return Err(collision::already_exists(&path));
+        }
+        Ok(path)
+    }
+    /// The kept archive of a tarball, zip or 7z archive is the download's only output file, so the collision policy applies to it as usual.
+    /// Alongside a decompressed file, it's replaced along with that file and must otherwise be vacant.
+    #[cfg(feature = "unarchive")]
+    fn resolve_kept_archive(&mut self, path: PathBuf, settings: &Settings) -> Result<PathBuf, DownloadError> {
+        if self.path.is_none() || self.collision_policy.unwrap_or(settings.collision_policy) == CollisionPolicy::Rename {
+            return self.resolve_collision(path, settings);
+        }
+        if !self.overwrite {
+            ensure_vacant(&path)?;
+        }
+        if !settings.claims.claim(&path) {
+            return Err(collision::already_exists(&path));
+        }
+        Ok(path)
+    }
+    /// Find an existing output file which matches the download's checksums, without contacting the server
+    #[cfg(feature = "verification")]
+    fn verify_existing(&mut self, id: usize, settings: &Settings) -> Result<Option<PathBuf>, DownloadError> {
+        #[cfg(feature = "unarchive")]
+        if self.decompress.is_some() {
+            return Ok(None);
+        }
+        if self.checksums.is_empty() || self.output.is_some() {
+            return Ok(None);
+        }
+        let Some(filename) = self.filename.clone().or_else(|| filename::infer(&self.url, &HeaderMap::new())) else {
+            return Ok(None);
+        };
+        let path = self.directory.clone().unwrap_or_else(|| CURRENT_DIR.clone()).join(filename);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        settings.events.emit(DownloadEvent::Verifying { id });
+        let file = File::open(&path).map_err(DownloadError::FileError)?;
+        if verify::verify_reader(&self.checksums, file, settings.buffer_size).map_err(DownloadError::FileError)? {
+            return Ok(Some(path));
+        }
+        log::info!("{} doesn't match its checksums", path.display());
+        self.invalid_existing = Some(path);
+        Ok(None)
+    }
+    /// The temporary file which downloaded data is written to, if it isn't written straight to a file supplied by the caller
+    fn download_path(&self) -> Option<&PathBuf> {
+        #[cfg(feature = "unarchive")]
+        if let Some(archive_path) = &self.archive_path {
+            return Some(archive_path);
+        }
+        self.partial_path.as_ref()
+    }
+    async fn run(mut self, id: usize, settings: &Settings) -> Result<DownloadSummary, DownloadError> {
+        let start = Instant::now();
+        let token = settings.handle.token(id);
+        if token.is_cancelled() {
+            return Err(stopped(settings.handle.reason(id)));
+        }
+        #[cfg(feature = "verification")]
+        if settings.verify_existing {
+            if let Some(path) = self.verify_existing(id, settings)? {
+                log::info!("{} matches its checksums, skipping download", path.display());
+                return Ok(DownloadSummary {
+                    path: Some(path),
+                    bytes: 0,
+                    duration: start.elapsed(),
+                    skipped: true,
+                    checksum_source: self.checksum_source,
+                });
+            }
+        }
+        let probe = probe::probe(&settings.client, id, &self.url, self.headers.as_deref());
+        let metadata = settings.until_stopped(id, probe).await.0?;
+        #[cfg(feature = "signatures")]
+        self.fetch_signature(id, settings).await?;
+        #[cfg(feature = "verification")]
+        self.fetch_checksum(id, &metadata, settings).await?;
+        #[cfg(feature = "unarchive")]
+        if self.decompress == Some(ArchiveFormat::Auto) {
+            self.detect_format(id, &metadata, settings).await?;
+        }
+        self.fill_output(&metadata, settings)?;
+        #[cfg(feature = "unarchive")]
+        let path = match &self.decompress {
+            Some(format) if format.is_multi_file() => Some(self.directory.clone().unwrap_or_else(|| CURRENT_DIR.clone())),
+            _ => self.path.clone(),
+        };
+        #[cfg(not(feature = "unarchive"))]
+        let path = self.path.clone();
+        if self.skip {
+            #[cfg(feature = "unarchive")]
+            let existing = self.path.as_ref().or(self.kept_archive.as_ref());
+            #[cfg(not(feature = "unarchive"))]
+            let existing = self.path.as_ref();
+            log::info!("{} already exists, skipping download", existing.unwrap().display());
+            return Ok(DownloadSummary {
+                path,
+                bytes: 0,
+                duration: start.elapsed(),
+                skipped: true,
+                // The existing file has only been verified if the policy demanded it
+                #[cfg(feature = "verification")]
+                checksum_source: self
+                    .checksum_source
+                    .filter(|_| self.collision_policy.unwrap_or(settings.collision_policy) == CollisionPolicy::SkipIfValid),
+            });
+        }
+        self.fill_length(metadata)?;
+        #[allow(unused_mut)]
+        let mut partial_paths = Vec::from_iter(self.partial_path.clone());
+        #[cfg(feature = "unarchive")]
+        partial_paths.extend(self.archive_path.clone());
+        let state_path = self.state_path.clone();
+        #[cfg(feature = "verification")]
+        let checksum_source = self.checksum_source.clone();
+
+        let (result, stop) = settings.until_stopped(id, self.fetch(id, settings)).await;
+        let keep_partial = match (&result, stop) {
+            (Ok(_), _) => true,
+            (Err(_), Some(Stop::Cancel)) => settings.cancel_policy == CancelPolicy::KeepPartial,
+            // Partial data is only worth keeping if the download can be resumed later, even when it was paused
+            (Err(_), _) => state_path.as_ref().is_some_and(|path| path.exists()),
+        };
+        if !keep_partial {
+            for partial_path in partial_paths.iter().chain(&state_path) {
+                if let Err(e) = std::fs::remove_file(partial_path) {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        log::warn!("Failed to remove {}: {e}", partial_path.display());
+                    }
+                }
+            }
+        }
+        Ok(DownloadSummary {
+            path,
+            bytes: result?,
+            duration: start.elapsed(),
+            skipped: false,
+            #[cfg(feature = "verification")]
+            checksum_source,
+        })
+    }
+    async fn fetch(mut self, id: usize, settings: &Settings) -> Result<u64, DownloadError> {
+        self.preferred_threads = match self.content_length {
+            // Without a known length or range support, the file can't be split into ranges
+            Some(_) if self.accepts_ranges => self
+                .preferred_threads
+                .or_else(|| choose_threads(self.content_length, &self.url)),
+            _ => {
+                if self.preferred_threads.is_some_and(|threads| threads > 1) {
+                    log::warn!("{} does not support range requests, downloading with a single thread", self.url);
                 }
+                Some(1)
+            }
+        };
+        self.spawn(id, settings).await
+    }
+    fn fill_length(&mut self, metadata: Metadata) -> Result<(), DownloadError> {
+        let length = metadata.content_length;
+        let validators = Validators::from_headers(&metadata.headers);
+        let mismatch = match &self.resume_state {
+            Some(_) if !metadata.accepts_ranges => Some("the server no longer accepts range requests"),
+            Some(state) if !state.matches_remote(length, &validators) => Some("the remote file has changed"),
+            _ => None,
+        };
+        if let Some(reason) = mismatch {
+            self.discard_partial(reason)?;
+        }
+        if let (None, Some(length)) = (&self.resume_state, length) {
+            if self.state_path.is_some() && metadata.accepts_ranges {
+                self.resume_state = Some(ResumeState::new(&self.url, length, validators));
             }
-            let dir = self.directory.as_ref().unwrap_or(&*CURRENT_DIR);
-            let file = File::create_new(dir.join(filename)).map_err(DownloadError::FileError)?;
-            self.output = Some(file);
         }
+        self.content_length = length;
+        self.accepts_ranges = metadata.accepts_ranges;
+        self.url = Arc::new(metadata.url);
         Ok(())
     }
-    async fn spawn(self, client: &ClientWithMiddleware, #[cfg(feature = "render_progress")] main_bar: Option<ProgressBar>) -> Result<(), DownloadError> {
-        let mut chunks = threads::Chunks::new(self.preferred_threads.unwrap(), self.content_length.unwrap());
+    async fn spawn(mut self, id: usize, settings: &Settings) -> Result<u64, DownloadError> {
+        let buffer_size = settings.buffer_size;
+        let resume_chunks = self
+            .resume_state
+            .as_ref()
+            .map(|state| &state.chunks)
+            .filter(|chunks| !chunks.is_empty());
+        let target = match self.download_path() {
+            Some(path) => open_partial(path, resume_chunks.is_some())?,
+            None => self.output.take().unwrap(),
+        };
+
+        let length = self.content_length;
+        let mut chunks = match (resume_chunks, length) {
+            (Some(states), Some(length)) => threads::Chunks::from_states(states, length, target, buffer_size)?,
+            _ => threads::Chunks::new(self.preferred_threads.unwrap(), length, target, buffer_size)?,
+        };
+        let state = match (self.resume_state, self.state_path) {
+            (Some(mut state), Some(path)) => {
+                state.chunks = chunks.states();
+                Some(resume::StateFile::create(path, state)?)
+            }
+            _ => None,
+        };
+        #[cfg(feature = "verification")]
+        let verify = !self.checksums.is_empty();
+        #[cfg(feature = "verification")]
+        if verify {
+            chunks.hash_with(self.checksums)?;
+        }
+        settings.events.emit(DownloadEvent::Started {
+            id,
+            content_length: length,
+            downloaded: chunks.downloaded(),
+        });
         chunks
-            .download(
-                client,
-                self.url,
-                self.headers,
-                #[cfg(feature = "render_progress")]
-                self.progress,
-            )
+            .download(&settings.client, id, self.url, self.headers, state.as_ref(), &settings.events)
             .await?;
+        if let Some(state) = state {
+            state.remove()?;
+        }
+        let bytes = chunks.downloaded();
         #[cfg(feature = "verification")]
-        if let Some(checksum) = self.checksum {
-            chunks.verify(checksum)?;
+        if verify {
+            settings.events.emit(DownloadEvent::Verifying { id });
+            chunks.verify()?;
+        }
+        #[cfg(feature = "signatures")]
+        if let Some(signature) = self.signature.filter(|signature| !signature.is_for_checksum_file()) {
+            settings.events.emit(DownloadEvent::Verifying { id });
+            chunks.verify_signature(&signature).await?;
         }
 
         #[cfg(feature = "unarchive")]
-        if let Some(archive) = self.decompress {
-            chunks.save_archive(self.directory, self.output.unwrap(), archive)?;
+        if let (Some(archive_format), Some(archive_path)) = (self.decompress, self.archive_path) {
+            settings.events.emit(DownloadEvent::Extracting { id });
+            let mut file = chunks.save()?;
+            file.rewind().map_err(DownloadError::FileError)?;
+            let output = match &self.partial_path {
+                Some(partial_path) => Some(open_partial(partial_path, false)?),
+                None => self.output,
+            };
+            archive_format.decompress_with(output, self.directory, file, &self.extract_options)?;
+            if let Some(kept_archive) = self.kept_archive {
+                if !self.overwrite {
+                    ensure_vacant(&kept_archive)?;
+                }
+                std::fs::rename(&archive_path, kept_archive).map_err(DownloadError::FileError)?;
+            } else {
+                std::fs::remove_file(archive_path).map_err(DownloadError::FileError)?;
+            }
         } else {
-            chunks.save(self.output.unwrap())?;
+            chunks.save()?;
         }
         #[cfg(not(feature = "unarchive"))]
-        chunks.save(self.output.unwrap())?;
+        chunks.save()?;
 
-        #[cfg(feature = "render_progress")]
-        if let Some(main_bar) = main_bar {
-            main_bar.inc(1);
+        if let (Some(partial_path), Some(path)) = (self.partial_path, self.path) {
+            if !self.overwrite {
+                ensure_vacant(&path)?;
+            }
+            std::fs::rename(partial_path, path).map_err(DownloadError::FileError)?;
         }
-        Ok(())
+        Ok(bytes)
     }
 }
 
-#[cfg(feature = "render_progress")]
-pub struct Progress {
-    total: Option<ProgressStyle>,
-    individual: Option<ProgressStyle>,
-}
-#[cfg(feature = "render_progress")]
-impl Default for Progress {
-    fn default() -> Self {
-        Self::new().with_default_total().with_default_individual()
+/// The error reported by a download which was stopped through a [`DownloadHandle`]
+fn stopped(reason: Option<Stop>) -> DownloadError {
+    match reason {
+        Some(Stop::Pause) => DownloadError::Paused,
+        Some(Stop::Abort) => DownloadError::Aborted,
+        _ => DownloadError::Cancelled,
     }
 }
-#[cfg(feature = "render_progress")]
-impl Progress {
-    const DEFAULT_TOTAL_PROGRESS: &'static str = "{elapsed_precise} {bar:30.cyan} {human_pos:>} / {human_len} ({percent}%)";
-    const DEFAULT_INDIVIDUAL_PROGRESS: &'static str = "{bar:30.blue/red} ({percent}%) {bytes:>12.green} / {total_bytes:<12.green} {bytes_per_sec:>13.blue} - ETA: {eta_precise}";
-    const PROGRESS_LINE: &'static str = "━╾╴─";
 
-    pub fn new() -> Self {
-        Self { total: None, individual: None }
-    }
-    pub fn with_default_total(mut self) -> Self {
-        self.total = Some(ProgressStyle::with_template(Progress::DEFAULT_TOTAL_PROGRESS).unwrap());
-        self
-    }
-    pub fn with_default_individual(mut self) -> Self {
-        self.individual = Some(
-            ProgressStyle::with_template(Progress::DEFAULT_INDIVIDUAL_PROGRESS)
-                .unwrap()
-                .progress_chars(Progress::PROGRESS_LINE),
-        );
-        self
-    }
-    pub fn with_total(mut self, style: ProgressStyle) -> Self {
-        self.total = Some(style);
-        self
-    }
-    pub fn with_individual(mut self, style: ProgressStyle) -> Self {
-        self.individual = Some(style);
-        self
+/// Open a temporary file for writing, keeping its contents if a download is being resumed into it
+fn open_partial(path: &Path, resume: bool) -> Result<File, DownloadError> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(!resume)
+        .open(path)
+        .map_err(DownloadError::FileError)
+}
+
+/// Claim a file to stage a download in. Staging files are left in place to be resumed, so they don't have to be vacant,
+/// but downloads of the same run sharing a remote file name are numbered so that they don't write to the same file.
+fn claim_staging(path: &Path, suffix: &str, settings: &Settings) -> PathBuf {
+    (0..)
+        .map(|number| with_suffix(&collision::numbered(path, number), suffix))
+        .find(|path| settings.claims.claim(path))
+        .unwrap()
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut filename = path.file_name().unwrap_or_default().to_os_string();
+    filename.push(suffix);
+    path.with_file_name(filename)
+}
+
+#[cfg(all(test, feature = "verification"))]
+mod tests {
+    use super::*;
+    use verify::{fixture::*, Checksum};
+
+    /// Run a download of `file.txt` from a port nothing listens on, which only succeeds if it never touches the network
+    fn download_existing(directory: &Path, checksums: &[&str]) -> Result<DownloadSummary, DownloadError> {
+        let mut download = Download::new("http://127.0.0.1:9/file.txt")
+            .unwrap()
+            .with_output_dir(directory.to_path_buf());
+        for checksum in checksums {
+            download = download.with_checksum(Checksum::new(*checksum).unwrap());
+        }
+        let downloader = Downloader::new(vec![download]).with_retries(0).with_verify_existing(true);
+        let mut reports = futures::executor::block_on(downloader.start_downloads_with_report()).unwrap();
+        reports.remove(0).result
     }
-    fn is_enabled(&self) -> bool {
-        self.total.is_some() || self.individual.is_some()
+
+    #[test]
+    fn existing_files_matching_every_checksum_are_not_downloaded() {
+        let directory = tempfile::tempdir().unwrap();
+        std::fs::write(directory.path().join("file.txt"), DATA).unwrap();
+        let summary = download_existing(directory.path(), &[SHA256, SHA512]).unwrap();
+        assert!(summary.skipped);
+        assert_eq!(summary.path, Some(directory.path().join("file.txt")));
     }
 }
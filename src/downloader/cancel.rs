@@ -0,0 +1,75 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Determines what happens to partially downloaded data when a download is cancelled
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CancelPolicy {
+    /// Delete temporary files and any saved resume state
+    #[default]
+    RemovePartial,
+    /// Leave temporary files on disk, so that they can be resumed if resuming is enabled
+    KeepPartial,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Stop {
+    Cancel,
+    Pause,
+    /// Stopped because another download failed
+    Abort,
+}
+
+/// Allows downloads to be stopped while a [`Downloader`](crate::Downloader) is running.
+/// Downloads are identified by their position in the `Downloader`.
+#[derive(Clone, Default)]
+pub struct DownloadHandle {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    token: CancellationToken,
+    all: Mutex<Option<Stop>>,
+    downloads: Mutex<HashMap<usize, (CancellationToken, Option<Stop>)>>,
+}
+
+impl DownloadHandle {
+    /// Cancel every download, handling partial data according to the [`CancelPolicy`]
+    pub fn cancel_all(&self) {
+        self.stop_all(Stop::Cancel);
+    }
+    pub fn cancel(&self, id: usize) {
+        self.stop(id, Stop::Cancel);
+    }
+    /// Stop every download. When resuming is enabled, partial data is kept so that paused downloads can be continued
+    /// by running them again. Otherwise nothing could continue them, so their partial data is removed.
+    pub fn pause_all(&self) {
+        self.stop_all(Stop::Pause);
+    }
+    pub fn pause(&self, id: usize) {
+        self.stop(id, Stop::Pause);
+    }
+    pub(crate) fn stop_all(&self, reason: Stop) {
+        self.inner.all.lock().unwrap().get_or_insert(reason);
+        self.inner.token.cancel();
+    }
+    fn stop(&self, id: usize, reason: Stop) {
+        let mut downloads = self.inner.downloads.lock().unwrap();
+        let (token, stop) = downloads.entry(id).or_insert_with(|| (self.inner.token.child_token(), None));
+        stop.get_or_insert(reason);
+        token.cancel();
+    }
+    pub(crate) fn token(&self, id: usize) -> CancellationToken {
+        let mut downloads = self.inner.downloads.lock().unwrap();
+        let (token, _) = downloads.entry(id).or_insert_with(|| (self.inner.token.child_token(), None));
+        token.clone()
+    }
+    /// Why a download was stopped, preferring a reason given specifically for it
+    pub(crate) fn reason(&self, id: usize) -> Option<Stop> {
+        let specific = self.inner.downloads.lock().unwrap().get(&id).and_then(|(_, stop)| *stop);
+        specific.or(*self.inner.all.lock().unwrap())
+    }
+}
@@ -0,0 +1,287 @@
+use super::{
+    events::DownloadId,
+    integrity::ChecksumSource,
+    verify::{Checksum, CsType},
+};
+use crate::error::{ChecksumError, DownloadError};
+use reqwest::{header::HeaderMap, Url};
+use reqwest_middleware::ClientWithMiddleware;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::OnceCell;
+
+/// The checksums published alongside a set of files, such as a `SHA256SUMS` file, a single-file `.sha256` file
+/// or BSD-style `SHA256 (file) = ...` lines
+#[derive(Clone, Default)]
+pub struct ChecksumFile {
+    entries: Vec<(Option<String>, Checksum)>,
+}
+
+impl ChecksumFile {
+    /// Parse the contents of a checksum file. Lines which aren't checksums, such as comments
+    /// or the armor of a signed file, are ignored.
+    ///
+    /// Lines without an algorithm tag have their algorithm guessed from the length of the hash, like [`Checksum::new`].
+    /// Use [`parse_with`](Self::parse_with) for files made by tools such as `b2sum` or `b3sum`.
+    pub fn parse(contents: &str) -> Self {
+        Self::parse_inner(contents, None)
+    }
+    /// Parse the contents of a checksum file whose untagged lines were made with a known algorithm
+    pub fn parse_with(contents: &str, checksum_type: CsType) -> Self {
+        Self::parse_inner(contents, Some(checksum_type))
+    }
+    fn parse_inner(contents: &str, checksum_type: Option<CsType>) -> Self {
+        let entries = contents
+            .lines()
+            .filter_map(|line| parse_line(line.trim_end_matches('\r'), checksum_type))
+            .collect();
+        Self { entries }
+    }
+    /// Find the checksum of a file by its name. Entries listed with a directory also match the bare file name,
+    /// and a file holding only a hash matches any name.
+    pub fn get(&self, filename: &str) -> Option<Checksum> {
+        let by_name = |matches: &dyn Fn(&str) -> bool| {
+            self.entries
+                .iter()
+                .find(|(name, _)| name.as_deref().is_some_and(matches))
+                .map(|(_, checksum)| checksum.clone())
+        };
+        by_name(&|name| name == filename)
+            .or_else(|| by_name(&|name| name.rsplit('/').next() == Some(filename)))
+            .or_else(|| match self.entries.as_slice() {
+                [(None, checksum)] => Some(checksum.clone()),
+                _ => None,
+            })
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+fn parse_line(line: &str, checksum_type: Option<CsType>) -> Option<(Option<String>, Checksum)> {
+    let line = line.trim_start();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    // BSD style: `SHA256 (name) = hash`
+    if let Some((tag, rest)) = line.split_once(" (") {
+        if let Some((name, hash)) = rest.rsplit_once(") = ") {
+            let checksum_type = CsType::from_name(tag)?;
+            return is_hex(hash).then(|| (Some(normalize(name)), Checksum::new_inner(hash, checksum_type)));
+        }
+    }
+    // GNU style: `hash  name`, where a `*` before the name marks binary mode
+    // and a leading backslash means that the name contains escaped characters
+    let (escaped, line) = match line.strip_prefix('\\') {
+        Some(line) => (true, line),
+        None => (false, line),
+    };
+    let (hash, name) = match line.split_once(char::is_whitespace) {
+        Some((hash, name)) => (hash, Some(name.trim_start().trim_start_matches('*'))),
+        None => (line, None),
+    };
+    if !is_hex(hash) {
+        return None;
+    }
+    let name = name.filter(|name| !name.is_empty()).map(|name| match escaped {
+        true => unescape(name),
+        false => name.to_string(),
+    });
+    // Hashes of a different length than the expected algorithm's are still guessed
+    let checksum = checksum_type
+        .and_then(|checksum_type| Checksum::from_digest(hash, checksum_type).ok())
+        .or_else(|| Checksum::new(hash).ok())?;
+    Some((name.as_deref().map(normalize), checksum))
+}
+
+/// Recognize the algorithm of a checksum file from its name, such as `SHA512SUMS`, `B2SUMS` or `file.b3`
+fn algorithm_from_name(name: &str) -> Option<CsType> {
+    let name = name.to_ascii_lowercase();
+    let algorithm = name
+        .strip_suffix("sums")
+        .or_else(|| name.strip_suffix("sum"))
+        .or_else(|| name.rsplit_once('.').map(|(_, extension)| extension))?;
+    match algorithm {
+        // The names used by b2sum and b3sum
+        "b2" => Some(CsType::Blake2b),
+        "b3" => Some(CsType::Blake3),
+        _ => CsType::from_name(algorithm),
+    }
+}
+
+fn is_hex(hash: &str) -> bool {
+    !hash.is_empty() && hash.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn normalize(name: &str) -> String {
+    name.trim_start_matches("./").to_string()
+}
+
+fn unescape(name: &str) -> String {
+    let mut unescaped = String::with_capacity(name.len());
+    let mut chars = name.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some(other) => other,
+            None => '\\',
+        });
+    }
+    unescaped
+}
+
+/// Checksum files fetched during a run, so that a file listing the checksums of several downloads is only fetched once.
+/// Each file is fetched through its own cell, so that different files can be fetched at the same time.
+#[derive(Default)]
+pub(crate) struct ChecksumFiles(Mutex<HashMap<Url, Arc<FetchedFile>>>);
+
+/// The contents of a checksum file, once it has been fetched
+type FetchedFile = OnceCell<Arc<[u8]>>;
+
+impl ChecksumFiles {
+    /// Fetch a checksum file with the download's headers, unless it has already been fetched during this run
+    pub(crate) async fn fetch(&self, client: &ClientWithMiddleware, id: usize, url: &Url, headers: Option<&HeaderMap>) -> Result<Arc<[u8]>, DownloadError> {
+        let file = self.0.lock().unwrap().entry(url.clone()).or_default().clone();
+        let contents = file
+            .get_or_try_init(|| async {
+                log::debug!("Fetching checksum file {url}");
+                let mut request = client.get(url.clone()).with_extension(DownloadId(id));
+                if let Some(headers) = headers {
+                    request = request.headers(headers.clone());
+                }
+                let response = request.send().await.map_err(DownloadError::RequestError)?;
+                let response = response.error_for_status().map_err(DownloadError::ReqwestError)?;
+                let contents = response.bytes().await.map_err(DownloadError::ReqwestError)?;
+                Ok::<_, DownloadError>(contents.as_ref().into())
+            })
+            .await?;
+        Ok(contents.clone())
+    }
+}
+
+/// Look up the checksum of the first of `filenames` listed in the checksum file fetched from `url`.
+/// The file's name determines the algorithm of untagged lines, if it names one.
+pub(crate) fn find(contents: &[u8], url: &Url, filenames: &[&str]) -> Result<(Checksum, ChecksumSource), ChecksumError> {
+    let name = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or_default();
+    let file = ChecksumFile::parse_inner(&String::from_utf8_lossy(contents), algorithm_from_name(name));
+    let checksum = filenames.iter().find_map(|filename| file.get(filename));
+    let checksum = checksum.map(|checksum| (checksum, ChecksumSource::ChecksumFile(url.clone())));
+    checksum.ok_or_else(|| ChecksumError::MissingEntry(filenames.first().copied().unwrap_or_default().to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::verify::fixture::*;
+
+    fn parsed(line: &str) -> Option<(Option<String>, bool)> {
+        parse_line(line, None).map(|(name, checksum)| (name, verifies(checksum)))
+    }
+
+    fn named(name: &str) -> Option<(Option<String>, bool)> {
+        Some((Some(name.to_string()), true))
+    }
+
+    #[test]
+    fn gnu_lines_are_parsed() {
+        assert_eq!(parsed(&format!("{SHA256}  file.txt")), named("file.txt"));
+        assert_eq!(
+            parsed(&format!("{SHA256}  name with  spaces.txt")),
+            named("name with  spaces.txt")
+        );
+        assert_eq!(parsed(&format!("{SHA256}  ./dir/file.txt")), named("dir/file.txt"));
+        assert_eq!(parsed(&format!("{}  file.txt", SHA256.to_uppercase())), named("file.txt"));
+        assert_eq!(parsed(&format!("{SHA1}  file.txt")), named("file.txt"));
+        // A file holding only a hash
+        assert_eq!(parsed(SHA256), Some((None, true)));
+    }
+
+    #[test]
+    fn binary_marker_is_removed() {
+        assert_eq!(parsed(&format!("{SHA256} *file.txt")), named("file.txt"));
+        assert_eq!(parsed(&format!("{SHA256} *./file.txt")), named("file.txt"));
+    }
+
+    #[test]
+    fn escaped_names_are_unescaped() {
+        assert_eq!(
+            parsed(&format!("\\{SHA256}  new\\nline\\\\back.txt")),
+            named("new\nline\\back.txt")
+        );
+        // Without the leading backslash, backslashes are part of the name
+        assert_eq!(parsed(&format!("{SHA256}  back\\slash.txt")), named("back\\slash.txt"));
+    }
+
+    #[test]
+    fn bsd_lines_are_parsed() {
+        assert_eq!(parsed(&format!("SHA256 (file.txt) = {SHA256}")), named("file.txt"));
+        assert_eq!(parsed(&format!("SHA256 (name (1).txt) = {SHA256}")), named("name (1).txt"));
+        assert_eq!(parsed(&format!("SHA1 (file.txt) = {SHA1}")), named("file.txt"));
+        // The tag decides the algorithm, so a hash of the wrong length doesn't verify
+        assert_eq!(
+            parsed(&format!("SHA256 (file.txt) = {SHA1}")),
+            Some((Some("file.txt".into()), false))
+        );
+        assert_eq!(parsed(&format!("UNKNOWN (file.txt) = {SHA256}")), None);
+    }
+
+    #[test]
+    fn other_lines_are_ignored() {
+        assert_eq!(parsed(""), None);
+        assert_eq!(parsed("# SHA256 checksums"), None);
+        assert_eq!(parsed("-----BEGIN PGP SIGNED MESSAGE-----"), None);
+        assert_eq!(parsed("Hash: SHA512"), None);
+        assert_eq!(parsed("SHA256 (file.txt) = not-a-hash"), None);
+    }
+
+    #[test]
+    fn files_are_looked_up_by_name() {
+        let file = ChecksumFile::parse(&format!(
+            "-----BEGIN PGP SIGNED MESSAGE-----\r\n\r\n{SHA1}  other.txt\r\n{SHA256}  dir/file.txt\r\n"
+        ));
+        assert!(verifies(file.get("dir/file.txt").unwrap()));
+        assert!(verifies(file.get("file.txt").unwrap()));
+        assert!(file.get("missing.txt").is_none());
+        assert!(verifies(ChecksumFile::parse(&format!("{SHA256}\n")).get("anything").unwrap()));
+        assert!(ChecksumFile::parse("no checksums here\n").is_empty());
+    }
+
+    #[test]
+    fn untagged_lines_use_the_given_algorithm() {
+        let contents = format!("{BLAKE2B}  hello.txt\n");
+        // A 512 bit hash is guessed to be SHA-512 without a hint
+        assert!(!verifies(ChecksumFile::parse(&contents).get("hello.txt").unwrap()));
+        assert!(verifies(
+            ChecksumFile::parse_with(&contents, CsType::Blake2b).get("hello.txt").unwrap()
+        ));
+    }
+
+    #[test]
+    fn algorithm_is_recognized_from_the_file_name() {
+        assert_eq!(algorithm_from_name("B2SUMS"), Some(CsType::Blake2b));
+        assert_eq!(algorithm_from_name("file.b3"), Some(CsType::Blake3));
+        assert_eq!(algorithm_from_name("SHA512SUMS"), Some(CsType::Sha512));
+        assert_eq!(algorithm_from_name("sha256sum"), Some(CsType::Sha256));
+        assert_eq!(algorithm_from_name("file.sha1"), Some(CsType::Sha1));
+        assert_eq!(algorithm_from_name("CHECKSUMS"), None);
+    }
+
+    #[test]
+    fn find_uses_the_url_for_the_algorithm() {
+        let contents = format!("{BLAKE2B}  hello.txt\n");
+        let url = Url::parse("https://example.com/release/B2SUMS").unwrap();
+        let (checksum, _) = find(contents.as_bytes(), &url, &["hello.txt"]).unwrap();
+        assert!(verifies(checksum));
+        assert!(matches!(find(contents.as_bytes(), &url, &["other.txt"]), Err(ChecksumError::MissingEntry(name)) if name == "other.txt"));
+    }
+}
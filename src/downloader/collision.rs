@@ -0,0 +1,105 @@
+use crate::error::DownloadError;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+/// Determines what happens when a download's output file already exists
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Fail the download, leaving the existing file untouched
+    #[default]
+    Fail,
+    /// Replace the existing file once the new one has been downloaded
+    Overwrite,
+    /// Keep the existing file and skip the download
+    Skip,
+    /// Keep the existing file and skip the download if it matches the download's checksums, otherwise replace it.
+    /// Downloads without a checksum are skipped whenever the file exists.
+    #[cfg(feature = "verification")]
+    SkipIfValid,
+    /// Save the download under the first free name of the form `name (1).ext`
+    Rename,
+}
+
+/// Output and staging paths chosen by the downloads of a single run, so that downloads can't collide with each other
+#[derive(Default)]
+pub(crate) struct Claims(Mutex<HashSet<PathBuf>>);
+
+impl Claims {
+    /// Claim a path, returning false if another download has already claimed it
+    pub(crate) fn claim(&self, path: &Path) -> bool {
+        self.0.lock().unwrap().insert(path.to_path_buf())
+    }
+}
+
+/// Insert a number before a path's extension, e.g. `archive (2).tar.gz`
+pub(crate) fn numbered(path: &Path, number: usize) -> PathBuf {
+    let Some(name) = path.file_name().map(|name| name.to_string_lossy()).filter(|_| number > 0) else {
+        return path.to_path_buf();
+    };
+    // A leading dot marks a hidden file rather than an extension
+    let split = match name.rfind('.').filter(|&index| index > 0) {
+        Some(index) => match name[..index].strip_suffix(".tar") {
+            Some(stem) if !stem.is_empty() => stem.len(),
+            _ => index,
+        },
+        None => name.len(),
+    };
+    let (stem, extension) = name.split_at(split);
+    path.with_file_name(format!("{stem} ({number}){extension}"))
+}
+
+pub(crate) fn ensure_vacant(path: &Path) -> Result<(), DownloadError> {
+    if path.exists() {
+        return Err(already_exists(path));
+    }
+    Ok(())
+}
+
+pub(crate) fn already_exists(path: &Path) -> DownloadError {
+    let message = format!("{} already exists", path.display());
+    DownloadError::FileError(std::io::Error::new(std::io::ErrorKind::AlreadyExists, message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn numbered_name(name: &str, number: usize) -> String {
+        numbered(Path::new(name), number).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn numbers_go_before_the_extension() {
+        assert_eq!(numbered_name("file.txt", 1), "file (1).txt");
+        assert_eq!(numbered_name("v1.2.txt", 2), "v1.2 (2).txt");
+        assert_eq!(numbered_name("dir/file.txt", 3), "dir/file (3).txt");
+        assert_eq!(numbered_name("README", 1), "README (1)");
+        assert_eq!(numbered_name("file.txt", 0), "file.txt");
+    }
+
+    #[test]
+    fn compressed_tarballs_keep_their_whole_extension() {
+        assert_eq!(numbered_name("archive.tar.gz", 2), "archive (2).tar.gz");
+        assert_eq!(numbered_name("v1.2.tar.zst", 1), "v1.2 (1).tar.zst");
+        assert_eq!(numbered_name("archive.tar", 1), "archive (1).tar");
+        // Without a stem, `.tar` is the name rather than part of the extension
+        assert_eq!(numbered_name(".tar.gz", 1), ".tar (1).gz");
+    }
+
+    #[test]
+    fn hidden_files_keep_their_leading_dot() {
+        assert_eq!(numbered_name(".bashrc", 1), ".bashrc (1)");
+        assert_eq!(numbered_name(".config.json", 1), ".config (1).json");
+    }
+
+    #[test]
+    fn paths_can_only_be_claimed_once() {
+        let claims = Claims::default();
+        assert!(claims.claim(Path::new("dir/file.txt")));
+        assert!(claims.claim(Path::new("dir/file (1).txt")));
+        assert!(!claims.claim(Path::new("dir/file.txt")));
+    }
+}
@@ -1,11 +1,66 @@
 use crate::error::ArchiveError;
+use glob::{MatchOptions, Pattern};
 use std::{
     fs::File,
-    io::{Read, Seek, SeekFrom, Write},
-    path::PathBuf,
+    io::{BufRead, BufReader, Read, Seek},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+/// The number of bytes needed to identify an archive's format
+pub(crate) const DETECTION_LENGTH: usize = 4096;
+
+const EXTENSIONS: &[(&str, ArchiveFormat)] = &[
+    (".tar.gz", ArchiveFormat::TarGz),
+    (".tgz", ArchiveFormat::TarGz),
+    (".tar.bz2", ArchiveFormat::TarBz2),
+    (".tbz2", ArchiveFormat::TarBz2),
+    (".tbz", ArchiveFormat::TarBz2),
+    (".tar.xz", ArchiveFormat::TarXz),
+    (".txz", ArchiveFormat::TarXz),
+    (".tar.zst", ArchiveFormat::TarZst),
+    (".tzst", ArchiveFormat::TarZst),
+    #[cfg(feature = "lz4")]
+    (".tar.lz4", ArchiveFormat::TarLz4),
+    (".tar", ArchiveFormat::Tar),
+    (".zip", ArchiveFormat::Zip),
+    (".gz", ArchiveFormat::Gz),
+    (".bz2", ArchiveFormat::Bz2),
+    (".xz", ArchiveFormat::Xz),
+    (".zst", ArchiveFormat::Zst),
+    (".lzma", ArchiveFormat::Lzma),
+    #[cfg(feature = "lz4")]
+    (".lz4", ArchiveFormat::Lz4),
+    #[cfg(feature = "brotli")]
+    (".br", ArchiveFormat::Brotli),
+    #[cfg(feature = "sevenz")]
+    (".7z", ArchiveFormat::SevenZ),
+];
+
+const CONTENT_TYPES: &[(&str, ArchiveFormat)] = &[
+    ("application/zip", ArchiveFormat::Zip),
+    ("application/x-zip-compressed", ArchiveFormat::Zip),
+    ("application/x-tar", ArchiveFormat::Tar),
+    ("application/x-compressed-tar", ArchiveFormat::TarGz),
+    ("application/gzip", ArchiveFormat::Gz),
+    ("application/x-gzip", ArchiveFormat::Gz),
+    ("application/x-bzip2", ArchiveFormat::Bz2),
+    ("application/x-xz", ArchiveFormat::Xz),
+    ("application/zstd", ArchiveFormat::Zst),
+    ("application/x-lzma", ArchiveFormat::Lzma),
+    #[cfg(feature = "lz4")]
+    ("application/x-lz4", ArchiveFormat::Lz4),
+    #[cfg(feature = "brotli")]
+    ("application/x-brotli", ArchiveFormat::Brotli),
+    #[cfg(feature = "sevenz")]
+    ("application/x-7z-compressed", ArchiveFormat::SevenZ),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ArchiveFormat {
+    /// Detect the format from the file's contents, falling back to its name and `Content-Type`.
+    /// Downloads in an unrecognized format are saved without being extracted.
+    Auto,
     Tar,
     TarBz2,
     TarGz,
@@ -16,121 +71,961 @@ pub enum ArchiveFormat {
     Gz,
     Bz2,
     Zst,
+    /// The legacy `.lzma` format, also known as LZMA-alone
+    Lzma,
+    #[cfg(feature = "lz4")]
+    TarLz4,
+    #[cfg(feature = "lz4")]
+    Lz4,
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "sevenz")]
+    SevenZ,
 }
 
-impl<'a> ArchiveFormat {
-    pub fn decompress(&self, file: File, path: Option<PathBuf>, data: &'a mut [&'a [u8]]) -> Result<(), ArchiveError> {
+/// Controls which entries of a tarball, zip or 7z archive are extracted, and where they're extracted to.
+/// Tarballs only store the data of hard linked files once, so hard links to an entry which is left out are skipped.
+#[derive(Debug, Clone, Default)]
+pub struct ExtractOptions {
+    strip_components: usize,
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    subdirectory: Option<PathBuf>,
+    keep_archive: bool,
+}
+
+impl ExtractOptions {
+    /// Remove leading components from the path of each entry, like tar's `--strip-components`.
+    /// Entries with no components left, such as the top level directory, are skipped.
+    pub fn with_strip_components(mut self, components: usize) -> Self {
+        self.strip_components = components;
+        self
+    }
+    /// Only extract entries matching a glob pattern such as `bin/*`. Patterns are matched against entry paths
+    /// after components have been stripped, and a pattern matching a directory also matches everything in it.
+    pub fn with_include(mut self, pattern: &str) -> Result<Self, ArchiveError> {
+        self.include.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+    /// Skip entries matching a glob pattern, even if they match an included pattern
+    pub fn with_exclude(mut self, pattern: &str) -> Result<Self, ArchiveError> {
+        self.exclude.push(Pattern::new(pattern)?);
+        Ok(self)
+    }
+    /// Extract into a subdirectory of the output directory
+    pub fn with_subdirectory(mut self, subdirectory: impl Into<PathBuf>) -> Self {
+        self.subdirectory = Some(subdirectory.into());
+        self
+    }
+    /// Keep the downloaded archive alongside the extracted files instead of deleting it. This also applies to single-file formats.
+    /// The collision policy applies to the archive before the download starts. For tarballs, zip and 7z archives it's the only file
+    /// the policy can check, so an existing archive can also cause the download to be skipped.
+    pub fn with_keep_archive(mut self, keep: bool) -> Self {
+        self.keep_archive = keep;
+        self
+    }
+    pub(crate) fn keep_archive(&self) -> bool {
+        self.keep_archive
+    }
+    /// Whether an entry, after stripping, passes the include and exclude patterns
+    fn is_selected(&self, path: &Path) -> bool {
+        let options = MatchOptions {
+            require_literal_separator: true,
+            ..Default::default()
+        };
+        let matches = |patterns: &[Pattern]| {
+            path.ancestors()
+                .any(|path| patterns.iter().any(|pattern| pattern.matches_path_with(path, options)))
+        };
+        (self.include.is_empty() || matches(&self.include)) && !matches(&self.exclude)
+    }
+}
+
+impl ArchiveFormat {
+    pub fn decompress(&self, file: Option<File>, path: Option<PathBuf>, data: impl Read + Seek) -> Result<(), ArchiveError> {
+        self.decompress_with(file, path, data, &ExtractOptions::default())
+    }
+    /// Decompress an archive, applying [`ExtractOptions`] to the entries of multi-file archives
+    pub fn decompress_with(&self, file: Option<File>, path: Option<PathBuf>, mut data: impl Read + Seek, options: &ExtractOptions) -> Result<(), ArchiveError> {
+        if let Self::Auto = self {
+            let mut prefix = Vec::new();
+            (&mut data).take(DETECTION_LENGTH as u64).read_to_end(&mut prefix)?;
+            data.rewind()?;
+            let format = Self::detect(&prefix).ok_or(ArchiveError::UnrecognizedFormat)?;
+            return format.decompress_with(file, path, data, options);
+        }
         log::debug!("Decompressing archive");
-        let path = || path.unwrap_or(std::env::current_dir().unwrap());
-        let mut archive_output = if matches!(self, Self::Tar | Self::TarBz2 | Self::TarGz | Self::TarXz | Self::TarZst) {
-            Output::Tarball(Vec::new())
+        let root = || Root::new(&path.unwrap_or(std::env::current_dir().unwrap()), options);
+        let reader = BufReader::new(data);
+        // Zip and 7z archives need random access, so they're read directly
+        match self {
+            Self::Zip => return unzip(reader, &root()?),
+            #[cfg(feature = "sevenz")]
+            Self::SevenZ => return un7z(reader, &root()?),
+            _ => (),
+        }
+        let mut decompressor = self.decoder(reader)?;
+        if self.is_multi_file() {
+            untar(decompressor, &root()?)
         } else {
-            Output::File(file)
+            let mut file = file.ok_or(ArchiveError::UnarchiveError)?;
+            std::io::copy(&mut decompressor, &mut file)?;
+            Ok(())
+        }
+    }
+    /// Identify an archive from its first bytes. A compressed tarball is only recognized as one
+    /// if enough of it can be decompressed to find the tar header, otherwise it's treated as a single compressed file.
+    pub fn detect(data: &[u8]) -> Option<Self> {
+        let format = match data {
+            [0x1f, 0x8b, ..] => Self::Gz,
+            [b'B', b'Z', b'h', ..] => Self::Bz2,
+            [0xfd, b'7', b'z', b'X', b'Z', 0, ..] => Self::Xz,
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Self::Zst,
+            // LZMA-alone has no magic number, but almost every file uses the default properties
+            [0x5d, 0, 0, ..] => Self::Lzma,
+            #[cfg(feature = "lz4")]
+            [0x04, 0x22, 0x4d, 0x18, ..] => Self::Lz4,
+            #[cfg(feature = "sevenz")]
+            [b'7', b'z', 0xbc, 0xaf, 0x27, 0x1c, ..] => return Some(Self::SevenZ),
+            [b'P', b'K', 3, 4, ..] | [b'P', b'K', 5, 6, ..] | [b'P', b'K', 7, 8, ..] => return Some(Self::Zip),
+            _ if is_tar(data) => return Some(Self::Tar),
+            _ => return None,
         };
-        let reader = SliceReader::new(data);
-        match self {
-            Self::Bz2 | Self::TarBz2 => {
-                let mut decompressor = bzip2::read::BzDecoder::new(reader);
-                std::io::copy(&mut decompressor, &mut archive_output)?;
-            }
-            Self::Gz | Self::TarGz => {
-                let mut decompressor = flate2::read::GzDecoder::new(reader);
-                std::io::copy(&mut decompressor, &mut archive_output)?;
+        let mut contents = Vec::new();
+        // The data is usually truncated, so decompression is expected to fail once it runs out
+        let _ = format
+            .decoder(data)
+            .and_then(|decoder| decoder.take(TAR_HEADER_LENGTH).read_to_end(&mut contents));
+        match format.tarball() {
+            Some(tarball) if is_tar(&contents) => Some(tarball),
+            _ => Some(format),
+        }
+    }
+    /// Identify a remote archive from its first bytes, falling back to its file name and `Content-Type`
+    pub(crate) fn identify(data: &[u8], filename: Option<&str>, content_type: Option<&str>) -> Option<Self> {
+        let filename = filename.map(str::to_ascii_lowercase);
+        let hint = filename
+            .and_then(|filename| EXTENSIONS.iter().find(|(ext, _)| filename.ends_with(ext)))
+            .or_else(|| {
+                let content_type = content_type?.split(';').next()?.trim().to_ascii_lowercase();
+                CONTENT_TYPES.iter().find(|(mime, _)| *mime == content_type)
+            })
+            .map(|(_, format)| *format);
+        match Self::detect(data) {
+            // Trust the name when the contents couldn't be decompressed far enough to find a tar header
+            Some(format) if hint.is_some() && format.tarball() == hint => hint,
+            Some(format) => Some(format),
+            None => hint,
+        }
+    }
+    fn decoder<'a>(&self, reader: impl BufRead + 'a) -> std::io::Result<Box<dyn Read + 'a>> {
+        Ok(match self {
+            Self::Bz2 | Self::TarBz2 => Box::new(bzip2::read::BzDecoder::new(reader)),
+            Self::Gz | Self::TarGz => Box::new(flate2::read::GzDecoder::new(reader)),
+            Self::Xz | Self::TarXz => Box::new(liblzma::read::XzDecoder::new(reader)),
+            Self::Zst | Self::TarZst => Box::new(zstd::stream::Decoder::with_buffer(reader)?),
+            Self::Lzma => {
+                let stream = liblzma::stream::Stream::new_lzma_decoder(u64::MAX)?;
+                Box::new(liblzma::read::XzDecoder::new_stream(reader, stream))
             }
-            Self::Xz | Self::TarXz => {
-                let mut decompressor = liblzma::read::XzDecoder::new(reader);
-                std::io::copy(&mut decompressor, &mut archive_output)?;
+            #[cfg(feature = "lz4")]
+            Self::Lz4 | Self::TarLz4 => Box::new(lz4_flex::frame::FrameDecoder::new(reader)),
+            #[cfg(feature = "brotli")]
+            Self::Brotli => Box::new(brotli_decompressor::Decompressor::new(reader, BROTLI_BUFFER_SIZE)),
+            // These aren't compressed as a whole
+            Self::Tar | Self::Zip | Self::Auto => Box::new(reader),
+            #[cfg(feature = "sevenz")]
+            Self::SevenZ => Box::new(reader),
+        })
+    }
+    /// The compressed tarball variant of a single-file compression format
+    fn tarball(&self) -> Option<Self> {
+        match self {
+            Self::Bz2 => Some(Self::TarBz2),
+            Self::Gz => Some(Self::TarGz),
+            Self::Xz => Some(Self::TarXz),
+            Self::Zst => Some(Self::TarZst),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Some(Self::TarLz4),
+            _ => None,
+        }
+    }
+    /// The file extension of single-file compression formats
+    pub(crate) fn extension(&self) -> Option<&'static str> {
+        match self {
+            Self::Bz2 => Some("bz2"),
+            Self::Gz => Some("gz"),
+            Self::Xz => Some("xz"),
+            Self::Zst => Some("zst"),
+            Self::Lzma => Some("lzma"),
+            #[cfg(feature = "lz4")]
+            Self::Lz4 => Some("lz4"),
+            #[cfg(feature = "brotli")]
+            Self::Brotli => Some("br"),
+            _ => None,
+        }
+    }
+    pub(crate) fn is_multi_file(&self) -> bool {
+        match self {
+            Self::Zip | Self::Tar | Self::TarBz2 | Self::TarGz | Self::TarXz | Self::TarZst => true,
+            #[cfg(feature = "lz4")]
+            Self::TarLz4 => true,
+            #[cfg(feature = "sevenz")]
+            Self::SevenZ => true,
+            _ => false,
+        }
+    }
+}
+
+const TAR_HEADER_LENGTH: u64 = 512;
+#[cfg(feature = "brotli")]
+const BROTLI_BUFFER_SIZE: usize = 4096;
+
+fn is_tar(data: &[u8]) -> bool {
+    data.get(257..262) == Some(b"ustar")
+}
+
+/// Unpack a tarball as it is read, so that it never has to be held in memory
+fn untar(reader: impl Read, root: &Root) -> Result<(), ArchiveError> {
+    let mut archive = tar::Archive::new(reader);
+    let mut directories = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.into_owned();
+        let Some(destination) = root.destination(&name)? else {
+            continue;
+        };
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        // A read-only directory would refuse its own contents, so its metadata is only applied once everything is extracted
+        if entry.header().entry_type() == tar::EntryType::Directory {
+            create_directory(&name, &destination)?;
+            let attributes = Attributes {
+                modified: entry
+                    .header()
+                    .mtime()
+                    .ok()
+                    .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds)),
+                mode: entry.header().mode().ok(),
+            };
+            directories.push((destination, attributes));
+            continue;
+        }
+        match entry.link_name()? {
+            // Hard links are relative to the root of the archive, rather than the directory they're in
+            Some(link) if entry.header().entry_type() == tar::EntryType::Link => {
+                let Some(source) = root.hard_link_source(&name, &link)? else {
+                    log::warn!(
+                        "Skipping {}, which links to {} which wasn't extracted",
+                        name.display(),
+                        link.display()
+                    );
+                    continue;
+                };
+                if destination.symlink_metadata().is_ok_and(|metadata| !metadata.is_dir()) {
+                    std::fs::remove_file(&destination)?;
+                }
+                std::fs::hard_link(source, &destination)?;
             }
-            Self::Zst | Self::TarZst => {
-                let mut decompressor = zstd::stream::Decoder::new(reader)?;
-                std::io::copy(&mut decompressor, &mut archive_output)?;
+            Some(link) => {
+                root.check_link(&name, &link)?;
+                entry.unpack(&destination)?;
             }
-            Self::Zip => {
-                let path = path();
-                let mut archive = zip::ZipArchive::new(reader).map_err(|_| ArchiveError::UnarchiveError)?;
-                for i in 0..archive.len() {
-                    let mut file = archive.by_index(i).map_err(|_| ArchiveError::UnarchiveError)?;
-                    let mut output = File::create(&path.join(file.name()))?;
-                    std::io::copy(&mut file, &mut output)?;
-                }
-                return Ok(());
+            None => {
+                entry.unpack(&destination)?;
             }
-            _ => (),
         }
-        if let Output::Tarball(data) = archive_output {
-            let mut archive = tar::Archive::new(data.as_slice());
-            archive.unpack(path())?;
+    }
+    restore_directories(directories)
+}
+
+/// Extract a zip archive, restoring directories, symlinks, modification times and Unix permissions
+fn unzip(reader: impl Read + Seek, root: &Root) -> Result<(), ArchiveError> {
+    let mut archive = zip::ZipArchive::new(reader).map_err(|_| ArchiveError::UnarchiveError)?;
+    let mut directories = Vec::new();
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|_| ArchiveError::UnarchiveError)?;
+        let name = file
+            .enclosed_name()
+            .ok_or_else(|| ArchiveError::UnsafeEntry(file.name().into()))?;
+        let Some(destination) = root.destination(&name)? else {
+            continue;
+        };
+        let attributes = Attributes {
+            modified: modified(&file),
+            mode: file.unix_mode(),
+        };
+        if file.is_dir() {
+            create_directory(&name, &destination)?;
+            directories.push((destination, attributes));
+        } else {
+            let symlink = file.is_symlink();
+            extract_file(root, &name, &destination, &mut file, symlink, attributes)?;
         }
-        Ok(())
     }
+    restore_directories(directories)
 }
 
-struct SliceReader<'a> {
-    slices: &'a mut [&'a [u8]],
-    index: usize,
-    inner_index: usize,
+/// Extract a 7z archive, restoring the same metadata as zip archives
+#[cfg(feature = "sevenz")]
+fn un7z(reader: impl Read + Seek, root: &Root) -> Result<(), ArchiveError> {
+    let mut directories = Vec::new();
+    let mut error = None;
+    let result = sevenz_rust::decompress_with_extract_fn(reader, &root.path, |entry, data, _| {
+        // Skip the remaining entries once one has failed, so that its error can be reported
+        if error.is_none() {
+            error = extract_7z_entry(root, entry, data, &mut directories).err();
+        }
+        Ok(error.is_none())
+    });
+    if let Some(error) = error {
+        return Err(error);
+    }
+    result.map_err(|_| ArchiveError::UnarchiveError)?;
+    restore_directories(directories)
 }
 
-impl<'a> SliceReader<'a> {
-    fn new(slices: &'a mut [&'a [u8]]) -> Self {
-        Self { slices, index: 0, inner_index: 0 }
+#[cfg(feature = "sevenz")]
+fn extract_7z_entry(root: &Root, entry: &sevenz_rust::SevenZArchiveEntry, data: &mut dyn Read, directories: &mut Vec<(PathBuf, Attributes)>) -> Result<(), ArchiveError> {
+    // Archivers running on Unix store the file mode in the upper half of the attributes
+    const UNIX_EXTENSION: u32 = 0x8000;
+    let name = Path::new(entry.name());
+    let Some(destination) = root.destination(name)? else {
+        // Entries of a solid archive share one stream, so a skipped entry's data must be read past
+        std::io::copy(data, &mut std::io::sink())?;
+        return Ok(());
+    };
+    let attributes = Attributes {
+        modified: Some(entry.last_modified_date())
+            .filter(|_| entry.has_last_modified_date)
+            .and_then(|time| u64::try_from(time.to_unix_time()).ok())
+            .map(|seconds| UNIX_EPOCH + Duration::from_secs(seconds)),
+        mode: Some(entry.windows_attributes() >> 16).filter(|_| entry.has_windows_attributes && entry.windows_attributes() & UNIX_EXTENSION != 0),
+    };
+    if entry.is_directory() {
+        create_directory(name, &destination)?;
+        directories.push((destination, attributes));
+        return Ok(());
     }
+    let symlink = attributes.mode.is_some_and(|mode| mode & 0o170000 == 0o120000);
+    extract_file(root, name, &destination, data, symlink, attributes)
 }
 
-impl<'a> Read for SliceReader<'a> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        while self.index < self.slices.len() {
-            match self.slices[self.index].read(buf) {
-                Ok(0) => self.index += 1,
-                r => return r,
-            }
+/// Create a directory entry's destination, refusing to follow a symlink extracted earlier onto another directory
+fn create_directory(name: &Path, destination: &Path) -> Result<(), ArchiveError> {
+    if destination.symlink_metadata().is_ok_and(|metadata| metadata.is_symlink()) {
+        return Err(unsafe_entry(name));
+    }
+    std::fs::create_dir_all(destination)?;
+    Ok(())
+}
+
+/// Metadata restored onto extracted files and directories
+struct Attributes {
+    modified: Option<SystemTime>,
+    mode: Option<u32>,
+}
+
+/// Write a file or symlink from an archive to its destination
+fn extract_file(root: &Root, name: &Path, destination: &Path, mut data: impl Read, symlink: bool, attributes: Attributes) -> Result<(), ArchiveError> {
+    if let Some(parent) = destination.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    // Never write through a link that was already in place
+    if destination.symlink_metadata().is_ok_and(|metadata| metadata.is_symlink()) {
+        std::fs::remove_file(destination)?;
+    }
+    if symlink {
+        let mut target = String::new();
+        data.read_to_string(&mut target)?;
+        root.check_link(name, Path::new(&target))?;
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&target, destination)?;
+            return Ok(());
         }
-        Ok(0)
     }
+    let mut output = File::create(destination)?;
+    std::io::copy(&mut data, &mut output)?;
+    if let Some(modified) = attributes.modified {
+        output.set_modified(modified)?;
+    }
+    #[cfg(unix)]
+    if let Some(mode) = attributes.mode {
+        set_mode(destination, mode)?;
+    }
+    Ok(())
 }
 
-impl<'a> Seek for SliceReader<'a> {
-    fn seek(&mut self, seek: SeekFrom) -> std::io::Result<u64> {
-        let pos = match seek {
-            SeekFrom::Start(offset) => offset as usize,
-            SeekFrom::End(offset) => self.slices.iter().map(|s| s.len()).sum::<usize>() - offset as usize,
-            SeekFrom::Current(offset) => (self.index + self.inner_index) + offset as usize,
-        };
+/// Extracting a directory's contents changes its modification time, so directories are updated last
+#[allow(unused_variables)]
+fn restore_directories(directories: Vec<(PathBuf, Attributes)>) -> Result<(), ArchiveError> {
+    // Directories can only be opened to set their modification time on Unix
+    #[cfg(unix)]
+    for (directory, attributes) in directories.into_iter().rev() {
+        // Metadata is never applied through a link, even one which replaced the directory after it was created
+        if directory.symlink_metadata()?.is_symlink() {
+            continue;
+        }
+        if let Some(modified) = attributes.modified {
+            File::open(&directory)?.set_modified(modified)?;
+        }
+        if let Some(mode) = attributes.mode {
+            set_mode(&directory, mode)?;
+        }
+    }
+    Ok(())
+}
+
+/// The modification time of a zip entry, preferring the precise Unix timestamp stored by most archivers
+fn modified(file: &zip::read::ZipFile) -> Option<SystemTime> {
+    let timestamp = file
+        .extra_data_fields()
+        .find_map(|zip::ExtraField::ExtendedTimestamp(timestamp)| timestamp.mod_time().copied());
+    if let Some(timestamp) = timestamp {
+        return Some(UNIX_EPOCH + Duration::from_secs(timestamp.into()));
+    }
+    // Otherwise fall back to the MS-DOS timestamp, which has no time zone and is treated as UTC
+    let time = file.last_modified()?;
+    let (year, month, day) = (i64::from(time.year()), i64::from(time.month()), i64::from(time.day()));
+    // Days since the Unix epoch in the proleptic Gregorian calendar
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    let seconds = days * 86400 + i64::from(time.hour()) * 3600 + i64::from(time.minute()) * 60 + i64::from(time.second());
+    Some(UNIX_EPOCH + Duration::from_secs(seconds.try_into().ok()?))
+}
 
-        self.index = 0;
-        self.inner_index = 0;
-        let mut total = 0;
+/// Restore an entry's permissions, leaving out the setuid, setgid and sticky bits
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode & 0o777))
+}
 
-        for (i, slice) in self.slices.iter().enumerate() {
-            if total + slice.len() > pos {
-                self.index = i;
-                self.inner_index = pos - total;
-                return Ok(pos as u64);
+/// The directory an archive is extracted into. Entries are refused if they would be written outside of it,
+/// either through their own path or through a symlink extracted earlier.
+struct Root<'a> {
+    path: PathBuf,
+    options: &'a ExtractOptions,
+}
+
+impl<'a> Root<'a> {
+    fn new(path: &Path, options: &'a ExtractOptions) -> Result<Self, ArchiveError> {
+        let path = match &options.subdirectory {
+            Some(subdirectory) => path.join(relative(subdirectory)?),
+            None => path.to_path_buf(),
+        };
+        std::fs::create_dir_all(&path)?;
+        Ok(Self { path: path.canonicalize()?, options })
+    }
+    /// Validate the name of an entry, returning the path it should be extracted to, or None if it's been left out
+    fn destination(&self, name: &Path) -> Result<Option<PathBuf>, ArchiveError> {
+        let Some(relative) = self.strip(name)?.filter(|relative| self.options.is_selected(relative)) else {
+            return Ok(None);
+        };
+        let parent = relative.parent().unwrap_or(Path::new(""));
+        if !self.resolve(parent).is_some_and(|resolved| resolved.starts_with(&self.path)) {
+            return Err(unsafe_entry(name));
+        }
+        Ok(Some(self.path.join(relative)))
+    }
+    /// Ensure that a symlink won't point outside of the root
+    fn check_link(&self, name: &Path, target: &Path) -> Result<(), ArchiveError> {
+        let relative = self.strip(name)?.unwrap_or_default();
+        let directory = relative.parent().unwrap_or(Path::new(""));
+        let within_root = self
+            .resolve(&directory.join(target))
+            .is_some_and(|resolved| resolved.starts_with(&self.path));
+        if target.has_root() || !within_root {
+            return Err(unsafe_entry(name));
+        }
+        Ok(())
+    }
+    /// The file a hard link should point to, which must be within the root. Returns None if the file was left out.
+    fn hard_link_source(&self, name: &Path, target: &Path) -> Result<Option<PathBuf>, ArchiveError> {
+        let Some(source) = self.strip(target)?.filter(|source| self.options.is_selected(source)) else {
+            return Ok(None);
+        };
+        match self.resolve(&source) {
+            Some(resolved) if resolved.starts_with(&self.path) => Ok(Some(resolved)),
+            _ => Err(unsafe_entry(name)),
+        }
+    }
+    /// An entry's path relative to the root once leading components have been stripped
+    fn strip(&self, name: &Path) -> Result<Option<PathBuf>, ArchiveError> {
+        let relative: PathBuf = relative(name)?.components().skip(self.options.strip_components).collect();
+        Ok(Some(relative).filter(|relative| relative.components().next().is_some()))
+    }
+    /// Join a relative path onto the root, following any symlinks which already exist along the way.
+    /// Returns None if the path leaves a component which doesn't exist yet through `..`, since a symlink
+    /// extracted later in its place would change where that leads.
+    fn resolve(&self, relative: &Path) -> Option<PathBuf> {
+        let mut resolved = self.path.clone();
+        let mut exists = true;
+        for component in relative.components() {
+            match component {
+                Component::ParentDir if !exists => return None,
+                Component::ParentDir => {
+                    resolved.pop();
+                }
+                Component::Normal(part) => {
+                    resolved.push(part);
+                    match resolved.canonicalize() {
+                        Ok(real) => resolved = real,
+                        Err(_) => exists = false,
+                    }
+                }
+                _ => (),
             }
-            total += slice.len();
         }
-
-        Err(std::io::Error::new(std::io::ErrorKind::InvalidInput, "Invalid seek"))
+        Some(resolved)
     }
 }
 
-enum Output {
-    Tarball(Vec<u8>),
-    File(File),
+/// An entry's path relative to the root, refusing absolute paths and parent directory components
+fn relative(name: &Path) -> Result<PathBuf, ArchiveError> {
+    name.components()
+        .filter(|component| *component != Component::CurDir)
+        .map(|component| match component {
+            Component::Normal(part) => Ok(part),
+            _ => Err(unsafe_entry(name)),
+        })
+        .collect()
 }
 
-impl Write for Output {
-    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
-        match self {
-            Self::Tarball(data) => data.write(buf),
-            Self::File(file) => file.write(buf),
+fn unsafe_entry(name: &Path) -> ArchiveError {
+    ArchiveError::UnsafeEntry(name.display().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read, Seek, Write};
+    use tar::EntryType;
+
+    const MTIME: u64 = 1_000_000_000;
+
+    /// Build a tarball by hand, so that entries can have names the tar crate would refuse to write
+    fn tarball(entries: &[(&str, EntryType, u32, &[u8])]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(Vec::new());
+        for &(name, entry_type, mode, data) in entries {
+            let mut header = tar::Header::new_ustar();
+            header.as_old_mut().name[..name.len()].copy_from_slice(name.as_bytes());
+            header.set_entry_type(entry_type);
+            header.set_mode(mode);
+            header.set_mtime(MTIME);
+            // Links store their target in the header rather than as data
+            let data = match entry_type {
+                EntryType::Symlink | EntryType::Link => {
+                    header.as_old_mut().linkname[..data.len()].copy_from_slice(data);
+                    &[]
+                }
+                _ => data,
+            };
+            header.set_size(data.len() as u64);
+            header.set_cksum();
+            builder.append(&header, data).unwrap();
         }
+        builder.into_inner().unwrap()
     }
 
-    fn flush(&mut self) -> std::io::Result<()> {
-        match self {
-            Self::Tarball(data) => data.flush(),
-            Self::File(file) => file.flush(),
+    fn extract(data: Vec<u8>, options: &ExtractOptions) -> (tempfile::TempDir, Result<(), ArchiveError>) {
+        let dir = tempfile::tempdir().unwrap();
+        let result = ArchiveFormat::Tar.decompress_with(None, Some(dir.path().into()), Cursor::new(data), options);
+        (dir, result)
+    }
+
+    /// Extract into a subdirectory of a temporary directory, so that anything escaping the output directory can be seen
+    fn extract_nested(data: Vec<u8>) -> (tempfile::TempDir, Result<(), ArchiveError>) {
+        extract(data, &ExtractOptions::default().with_subdirectory("out"))
+    }
+
+    fn is_unsafe(result: Result<(), ArchiveError>, entry: &str) -> bool {
+        matches!(result, Err(ArchiveError::UnsafeEntry(name)) if name == entry)
+    }
+
+    fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut encoder = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn zstd(data: &[u8]) -> Vec<u8> {
+        zstd::stream::encode_all(data, 0).unwrap()
+    }
+
+    /// A tarball with enough contents that compressing it doesn't fit it into a few bytes
+    fn large_tarball() -> Vec<u8> {
+        let contents: Vec<u8> = (0..u16::MAX).flat_map(u16::to_le_bytes).collect();
+        tarball(&[("file", EntryType::Regular, 0o644, &contents)])
+    }
+
+    #[test]
+    fn formats_are_detected_from_their_contents() {
+        let tar = tarball(&[("file", EntryType::Regular, 0o644, b"contents")]);
+        assert_eq!(ArchiveFormat::detect(&tar), Some(ArchiveFormat::Tar));
+        assert_eq!(ArchiveFormat::detect(&gzip(&tar)), Some(ArchiveFormat::TarGz));
+        assert_eq!(ArchiveFormat::detect(&gzip(b"plain text")), Some(ArchiveFormat::Gz));
+        assert_eq!(ArchiveFormat::detect(&zstd(&tar)), Some(ArchiveFormat::TarZst));
+        assert_eq!(ArchiveFormat::detect(&zstd(b"plain text")), Some(ArchiveFormat::Zst));
+        assert_eq!(ArchiveFormat::detect(b"PK\x03\x04rest of a zip"), Some(ArchiveFormat::Zip));
+        assert_eq!(ArchiveFormat::detect(b"plain text"), None);
+        assert_eq!(ArchiveFormat::detect(b""), None);
+    }
+
+    #[test]
+    fn tarballs_are_found_in_the_first_bytes() {
+        let data = gzip(&large_tarball());
+        assert_eq!(ArchiveFormat::detect(&data[..DETECTION_LENGTH]), Some(ArchiveFormat::TarGz));
+        let data = zstd(&large_tarball());
+        assert_eq!(ArchiveFormat::detect(&data[..DETECTION_LENGTH]), Some(ArchiveFormat::TarZst));
+        // Too little to decompress the tar header
+        assert_eq!(ArchiveFormat::detect(&gzip(&large_tarball())[..16]), Some(ArchiveFormat::Gz));
+    }
+
+    #[test]
+    fn tarball_names_win_over_bare_magic_numbers() {
+        let data = &gzip(&large_tarball())[..16];
+        assert_eq!(
+            ArchiveFormat::identify(data, Some("release.tar.gz"), None),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(
+            ArchiveFormat::identify(data, Some("RELEASE.TGZ"), None),
+            Some(ArchiveFormat::TarGz)
+        );
+        assert_eq!(ArchiveFormat::identify(data, Some("release.gz"), None), Some(ArchiveFormat::Gz));
+        assert_eq!(
+            ArchiveFormat::identify(data, None, Some("application/x-compressed-tar")),
+            Some(ArchiveFormat::TarGz)
+        );
+        // A name naming a different format doesn't override the contents
+        assert_eq!(
+            ArchiveFormat::identify(data, Some("release.zip"), None),
+            Some(ArchiveFormat::Gz)
+        );
+        assert_eq!(
+            ArchiveFormat::identify(data, Some("release.tar.zst"), None),
+            Some(ArchiveFormat::Gz)
+        );
+    }
+
+    #[test]
+    fn unrecognized_contents_fall_back_to_the_name_and_content_type() {
+        let data = b"not an archive";
+        assert_eq!(
+            ArchiveFormat::identify(data, Some("file.tar.xz"), None),
+            Some(ArchiveFormat::TarXz)
+        );
+        assert_eq!(
+            ArchiveFormat::identify(data, Some("file.bin"), Some("application/zip; charset=binary")),
+            Some(ArchiveFormat::Zip)
+        );
+        // The name is preferred over the Content-Type
+        assert_eq!(
+            ArchiveFormat::identify(data, Some("file.xz"), Some("application/zip")),
+            Some(ArchiveFormat::Xz)
+        );
+        assert_eq!(
+            ArchiveFormat::identify(data, Some("file.bin"), Some("application/octet-stream")),
+            None
+        );
+        assert_eq!(ArchiveFormat::identify(data, None, None), None);
+    }
+
+    /// Decompress a single compressed file, returning its contents
+    fn decompress_file(format: ArchiveFormat, data: Vec<u8>) -> Vec<u8> {
+        let mut file = tempfile::tempfile().unwrap();
+        format
+            .decompress(Some(file.try_clone().unwrap()), None, Cursor::new(data))
+            .unwrap();
+        let mut contents = Vec::new();
+        file.rewind().unwrap();
+        file.read_to_end(&mut contents).unwrap();
+        contents
+    }
+
+    #[test]
+    fn lzma_alone_is_decompressed() {
+        let options = liblzma::stream::LzmaOptions::new_preset(6).unwrap();
+        let stream = liblzma::stream::Stream::new_lzma_encoder(&options).unwrap();
+        let mut encoder = liblzma::write::XzEncoder::new_stream(Vec::new(), stream);
+        encoder.write_all(b"lzma contents").unwrap();
+        let data = encoder.finish().unwrap();
+        assert_eq!(ArchiveFormat::detect(&data), Some(ArchiveFormat::Lzma));
+        assert_eq!(decompress_file(ArchiveFormat::Auto, data), b"lzma contents");
+    }
+
+    #[cfg(feature = "lz4")]
+    fn lz4(data: &[u8]) -> Vec<u8> {
+        let mut encoder = lz4_flex::frame::FrameEncoder::new(Vec::new());
+        encoder.write_all(data).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4_is_decompressed() {
+        let data = lz4(b"lz4 contents");
+        assert_eq!(ArchiveFormat::detect(&data), Some(ArchiveFormat::Lz4));
+        assert_eq!(decompress_file(ArchiveFormat::Auto, data), b"lz4 contents");
+
+        let data = lz4(&tarball(&[("file", EntryType::Regular, 0o644, b"contents")]));
+        assert_eq!(ArchiveFormat::detect(&data), Some(ArchiveFormat::TarLz4));
+        let dir = tempfile::tempdir().unwrap();
+        ArchiveFormat::Auto
+            .decompress(None, Some(dir.path().into()), Cursor::new(data))
+            .unwrap();
+        assert_eq!(std::fs::read(dir.path().join("file")).unwrap(), b"contents");
+    }
+
+    #[cfg(feature = "brotli")]
+    #[test]
+    fn brotli_is_recognized_by_name_and_decompressed() {
+        let mut data = Vec::new();
+        brotli::BrotliCompress(&mut &b"brotli contents"[..], &mut data, &Default::default()).unwrap();
+        // Brotli has no magic number
+        assert_eq!(ArchiveFormat::detect(&data), None);
+        assert_eq!(
+            ArchiveFormat::identify(&data, Some("file.br"), None),
+            Some(ArchiveFormat::Brotli)
+        );
+        assert_eq!(decompress_file(ArchiveFormat::Brotli, data), b"brotli contents");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn zip_entries_keep_their_metadata() {
+        use std::os::unix::fs::PermissionsExt;
+        use zip::{write::FullFileOptions, DateTime, ZipWriter};
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        let options = FullFileOptions::default();
+        // MS-DOS timestamps have no time zone, and are read as UTC
+        let dos_time = DateTime::from_date_and_time(2020, 1, 2, 3, 4, 6).unwrap();
+        writer
+            .add_directory("bin/", options.clone().unix_permissions(0o750).last_modified_time(dos_time))
+            .unwrap();
+        let mut tool_options = options.clone().unix_permissions(0o755);
+        // The extended timestamp, with only the modification time present
+        let extended_timestamp = [&[1], &(MTIME as u32).to_le_bytes()[..]].concat();
+        tool_options.add_extra_data(0x5455, &extended_timestamp, false).unwrap();
+        writer.start_file("bin/tool", tool_options).unwrap();
+        writer.write_all(b"#!/bin/sh").unwrap();
+        writer.add_symlink("tool", "bin/tool", options.clone()).unwrap();
+        writer.start_file("deep/nested/file", options).unwrap();
+        writer.write_all(b"contents").unwrap();
+        let data = writer.finish().unwrap().into_inner();
+
+        let dir = tempfile::tempdir().unwrap();
+        ArchiveFormat::Zip
+            .decompress_with(None, Some(dir.path().into()), Cursor::new(data), &ExtractOptions::default())
+            .unwrap();
+        let bin = std::fs::metadata(dir.path().join("bin")).unwrap();
+        assert!(bin.is_dir());
+        assert_eq!(bin.permissions().mode() & 0o777, 0o750);
+        assert_eq!(bin.modified().unwrap(), UNIX_EPOCH + Duration::from_secs(1_577_934_246));
+        let tool = std::fs::metadata(dir.path().join("bin/tool")).unwrap();
+        assert_eq!(tool.permissions().mode() & 0o777, 0o755);
+        assert_eq!(tool.modified().unwrap(), UNIX_EPOCH + Duration::from_secs(MTIME));
+        assert_eq!(std::fs::read_link(dir.path().join("tool")).unwrap(), Path::new("bin/tool"));
+        assert_eq!(std::fs::read(dir.path().join("deep/nested/file")).unwrap(), b"contents");
+    }
+
+    #[test]
+    fn read_only_directories_are_restored_after_their_contents() {
+        let data = tarball(&[("ro/", EntryType::Directory, 0o555, b""), ("ro/file", EntryType::Regular, 0o644, b"contents")]);
+        let (dir, result) = extract(data, &ExtractOptions::default());
+        result.unwrap();
+        let directory = dir.path().join("ro");
+        assert_eq!(std::fs::read(directory.join("file")).unwrap(), b"contents");
+        let metadata = std::fs::metadata(&directory).unwrap();
+        assert_eq!(metadata.modified().unwrap(), UNIX_EPOCH + Duration::from_secs(MTIME));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            assert_eq!(metadata.permissions().mode() & 0o777, 0o555);
+            set_mode(&directory, 0o755).unwrap();
         }
     }
+
+    #[test]
+    fn hard_links_to_left_out_entries_are_skipped() {
+        let data = || {
+            tarball(&[
+                ("a/target", EntryType::Regular, 0o644, b"contents"),
+                ("b/link", EntryType::Link, 0o644, b"a/target"),
+                ("b/file", EntryType::Regular, 0o644, b"other"),
+            ])
+        };
+        let (dir, result) = extract(data(), &ExtractOptions::default());
+        result.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("b/link")).unwrap(), b"contents");
+
+        let (dir, result) = extract(data(), &ExtractOptions::default().with_include("b").unwrap());
+        result.unwrap();
+        assert!(!dir.path().join("a").exists());
+        assert!(!dir.path().join("b/link").exists());
+        assert_eq!(std::fs::read(dir.path().join("b/file")).unwrap(), b"other");
+
+        // Stripping a component leaves nothing of a top level target
+        let data = tarball(&[
+            ("target", EntryType::Regular, 0o644, b"contents"),
+            ("b/link", EntryType::Link, 0o644, b"target"),
+            ("b/file", EntryType::Regular, 0o644, b"other"),
+        ]);
+        let (dir, result) = extract(data, &ExtractOptions::default().with_strip_components(1));
+        result.unwrap();
+        assert!(!dir.path().join("link").exists());
+        assert_eq!(std::fs::read(dir.path().join("file")).unwrap(), b"other");
+    }
+
+    #[test]
+    fn parent_directory_components_are_refused() {
+        let data = tarball(&[("../evil", EntryType::Regular, 0o644, b"contents")]);
+        let (dir, result) = extract_nested(data);
+        assert!(is_unsafe(result, "../evil"));
+        assert!(!dir.path().join("evil").exists());
+
+        let data = tarball(&[("a/../../evil", EntryType::Regular, 0o644, b"contents")]);
+        let (dir, result) = extract_nested(data);
+        assert!(is_unsafe(result, "a/../../evil"));
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    #[test]
+    fn absolute_paths_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let target = dir.path().join("evil");
+        let name = target.to_str().unwrap();
+        let data = tarball(&[(name, EntryType::Regular, 0o644, b"contents")]);
+        let (_, result) = extract_nested(data);
+        assert!(is_unsafe(result, name));
+        assert!(!target.exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_outside_of_the_root_are_refused() {
+        let data = tarball(&[("link", EntryType::Symlink, 0o777, b".."), ("link/evil", EntryType::Regular, 0o644, b"contents")]);
+        let (dir, result) = extract_nested(data);
+        assert!(is_unsafe(result, "link"));
+        assert!(!dir.path().join("evil").exists());
+
+        let data = tarball(&[("link", EntryType::Symlink, 0o777, b"/tmp")]);
+        let (_, result) = extract_nested(data);
+        assert!(is_unsafe(result, "link"));
+
+        // `b/..` only leads back into the root while `b` isn't replaced by a symlink
+        let data = tarball(&[
+            ("a", EntryType::Symlink, 0o777, b"b/.."),
+            ("b", EntryType::Symlink, 0o777, b"."),
+            ("a/", EntryType::Directory, 0o751, b""),
+        ]);
+        let dir = tempfile::tempdir().unwrap();
+        let permissions = std::fs::metadata(dir.path()).unwrap().permissions();
+        let result = ArchiveFormat::Tar.decompress_with(
+            None,
+            Some(dir.path().join("out")),
+            Cursor::new(data),
+            &ExtractOptions::default(),
+        );
+        assert!(is_unsafe(result, "a"));
+        assert!(!dir.path().join("out/a").exists());
+        assert_eq!(std::fs::metadata(dir.path()).unwrap().permissions(), permissions);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn directories_are_not_created_through_symlinks() {
+        let data = tarball(&[
+            ("inner/", EntryType::Directory, 0o755, b""),
+            ("link", EntryType::Symlink, 0o777, b"inner"),
+            ("link/", EntryType::Directory, 0o700, b""),
+        ]);
+        let (dir, result) = extract(data, &ExtractOptions::default());
+        assert!(matches!(result, Err(ArchiveError::UnsafeEntry(_))));
+        use std::os::unix::fs::PermissionsExt;
+        assert_eq!(
+            std::fs::metadata(dir.path().join("inner")).unwrap().permissions().mode() & 0o777,
+            0o755
+        );
+    }
+
+    /// Build a solid 7z archive, where every file is compressed in one stream
+    #[cfg(feature = "sevenz")]
+    fn solid_7z(files: &[(&str, &'static [u8])]) -> Vec<u8> {
+        use sevenz_rust::{SevenZArchiveEntry, SevenZWriter, SourceReader};
+        let mut writer = SevenZWriter::new(Cursor::new(Vec::new())).unwrap();
+        let entries = files
+            .iter()
+            .map(|&(name, data)| {
+                let mut entry = SevenZArchiveEntry::new();
+                entry.name = name.to_string();
+                entry.has_stream = true;
+                entry.size = data.len() as u64;
+                entry
+            })
+            .collect();
+        let readers: Vec<_> = files.iter().map(|&(_, data)| SourceReader::new(data)).collect();
+        writer.push_archive_entries(entries, readers.into()).unwrap();
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[cfg(feature = "sevenz")]
+    #[test]
+    fn left_out_7z_entries_are_read_past() {
+        let data = solid_7z(&[("skipped.txt", b"skipped contents"), ("kept.txt", b"kept contents"), ("also.txt", b"more")]);
+        let dir = tempfile::tempdir().unwrap();
+        let options = ExtractOptions::default().with_exclude("skipped.txt").unwrap();
+        ArchiveFormat::SevenZ
+            .decompress_with(None, Some(dir.path().into()), Cursor::new(data), &options)
+            .unwrap();
+        assert!(!dir.path().join("skipped.txt").exists());
+        assert_eq!(std::fs::read(dir.path().join("kept.txt")).unwrap(), b"kept contents");
+        assert_eq!(std::fs::read(dir.path().join("also.txt")).unwrap(), b"more");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn files_under_an_existing_symlink_outside_of_the_root_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().join("out");
+        std::fs::create_dir(&root).unwrap();
+        std::os::unix::fs::symlink(dir.path(), root.join("link")).unwrap();
+        let data = tarball(&[("link/evil", EntryType::Regular, 0o644, b"contents")]);
+        let result = ArchiveFormat::Tar.decompress_with(None, Some(root), Cursor::new(data), &ExtractOptions::default());
+        assert!(is_unsafe(result, "link/evil"));
+        assert!(!dir.path().join("evil").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_within_the_root_are_followed() {
+        let data = tarball(&[
+            ("inner/", EntryType::Directory, 0o755, b""),
+            ("link", EntryType::Symlink, 0o777, b"inner"),
+            ("link/file", EntryType::Regular, 0o644, b"contents"),
+        ]);
+        let (dir, result) = extract(data, &ExtractOptions::default());
+        result.unwrap();
+        assert_eq!(std::fs::read(dir.path().join("inner/file")).unwrap(), b"contents");
+    }
+
+    #[test]
+    fn hard_links_outside_of_the_root_are_refused() {
+        let data = tarball(&[("link", EntryType::Link, 0o644, b"../target")]);
+        let (dir, result) = extract_nested(data);
+        assert!(is_unsafe(result, "../target"));
+        assert!(!dir.path().join("out/link").exists());
+
+        let data = tarball(&[("link", EntryType::Link, 0o644, b"/etc/passwd")]);
+        let (dir, result) = extract_nested(data);
+        assert!(is_unsafe(result, "/etc/passwd"));
+        assert!(!dir.path().join("out/link").exists());
+    }
 }
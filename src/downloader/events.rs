@@ -0,0 +1,102 @@
+use http::Extensions;
+use reqwest::{Request, Response, Url};
+use reqwest_middleware::{Middleware, Next};
+use std::sync::Arc;
+
+/// Something that happened to a download. Downloads are identified by their position in the [`Downloader`](crate::Downloader).
+#[derive(Debug, Clone)]
+pub enum DownloadEvent {
+    /// The download has been added to the queue and is waiting to start
+    Queued {
+        id: usize,
+        url: Url,
+    },
+    /// Data has started being transferred. `downloaded` is non-zero when a download is resumed.
+    Started {
+        id: usize,
+        content_length: Option<u64>,
+        downloaded: u64,
+    },
+    /// A chunk of the file received `bytes` more bytes
+    Progress {
+        id: usize,
+        chunk: usize,
+        bytes: u64,
+    },
+    /// A request failed with a transient error and is being sent again
+    Retrying {
+        id: usize,
+        attempt: u32,
+    },
+    /// The download, or a file already present at its output path, is being checked against its checksums or signature
+    Verifying {
+        id: usize,
+    },
+    /// The download is being decompressed or unpacked
+    Extracting {
+        id: usize,
+    },
+    Finished {
+        id: usize,
+    },
+    Failed {
+        id: usize,
+        error: String,
+    },
+}
+
+/// Receives events from a [`Downloader`](crate::Downloader) as downloads progress.
+/// Events are delivered from within the download tasks, so implementations should return quickly.
+pub trait ProgressObserver: Send + Sync {
+    fn on_event(&self, event: &DownloadEvent);
+}
+
+impl<F: Fn(&DownloadEvent) + Send + Sync> ProgressObserver for F {
+    fn on_event(&self, event: &DownloadEvent) {
+        self(event)
+    }
+}
+
+impl ProgressObserver for tokio::sync::mpsc::UnboundedSender<DownloadEvent> {
+    fn on_event(&self, event: &DownloadEvent) {
+        // The receiver being dropped just means nobody is interested in events anymore
+        let _ = self.send(event.clone());
+    }
+}
+
+/// Delivers events to every registered observer
+#[derive(Clone, Default)]
+pub(crate) struct Events {
+    observers: Vec<Arc<dyn ProgressObserver>>,
+}
+
+impl Events {
+    pub(crate) fn new(observers: Vec<Arc<dyn ProgressObserver>>) -> Self {
+        Self { observers }
+    }
+    pub(crate) fn emit(&self, event: DownloadEvent) {
+        self.observers.iter().for_each(|observer| observer.on_event(&event));
+    }
+}
+
+/// Attached to each request so that retries can be attributed to a download
+#[derive(Clone, Copy)]
+pub(crate) struct DownloadId(pub(crate) usize);
+
+#[derive(Clone, Copy)]
+struct Attempt(u32);
+
+/// Middleware placed inside the retry middleware, so that it sees every attempt of a request
+pub(crate) struct RetryEvents(pub(crate) Events);
+
+#[async_trait::async_trait]
+impl Middleware for RetryEvents {
+    async fn handle(&self, req: Request, extensions: &mut Extensions, next: Next<'_>) -> reqwest_middleware::Result<Response> {
+        let attempt = extensions.get::<Attempt>().map_or(0, |attempt| attempt.0 + 1);
+        extensions.insert(Attempt(attempt));
+        if let (1.., Some(&DownloadId(id))) = (attempt, extensions.get::<DownloadId>()) {
+            self.0.emit(DownloadEvent::Retrying { id, attempt });
+        }
+        next.run(req, extensions).await
+    }
+}
@@ -0,0 +1,169 @@
+use percent_encoding::percent_decode_str;
+use reqwest::{
+    header::{HeaderMap, CONTENT_DISPOSITION},
+    Url,
+};
+
+const RESERVED_CHARACTERS: [char; 9] = ['/', '\\', '<', '>', ':', '"', '|', '?', '*'];
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8", "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Determine the name of a remote file, preferring the name given by the server over the last segment of its URL
+pub(crate) fn infer(url: &Url, headers: &HeaderMap) -> Option<String> {
+    from_content_disposition(headers).or_else(|| from_url(url))
+}
+
+/// Read the filename from a `Content-Disposition` header, preferring the extended `filename*` parameter (RFC 6266)
+fn from_content_disposition(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(CONTENT_DISPOSITION)?;
+    let value = String::from_utf8_lossy(value.as_bytes());
+    let parameters = parameters(&value);
+    let extended = parameters
+        .iter()
+        .filter(|(name, _)| name == "filename*")
+        .find_map(|(_, value)| decode_extended(value).and_then(|name| sanitize(&name)));
+    extended.or_else(|| {
+        parameters
+            .iter()
+            .filter(|(name, _)| name == "filename")
+            .find_map(|(_, value)| sanitize(value))
+    })
+}
+
+fn from_url(url: &Url) -> Option<String> {
+    let segment = url.path_segments()?.next_back()?;
+    sanitize(&percent_decode_str(segment).decode_utf8_lossy())
+}
+
+/// Split the parameters following the disposition type into lowercased names and unquoted values
+fn parameters(value: &str) -> Vec<(String, String)> {
+    let mut parameters = Vec::new();
+    let mut chars = value.chars().skip_while(|&c| c != ';').peekable();
+    while chars.next().is_some() {
+        let name: String = chars.by_ref().take_while(|&c| c != '=').collect();
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'"').is_some() {
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => break,
+                    '\\' => value.extend(chars.next()),
+                    c => value.push(c),
+                }
+            }
+        }
+        while let Some(c) = chars.next_if(|&c| c != ';') {
+            value.push(c);
+        }
+        parameters.push((name.trim().to_ascii_lowercase(), value.trim().to_string()));
+    }
+    parameters
+}
+
+/// Decode an RFC 8187 extended value, e.g. `UTF-8''na%C3%AFve%20file.txt`
+fn decode_extended(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+    let bytes = percent_decode_str(encoded);
+    if charset.eq_ignore_ascii_case("utf-8") {
+        bytes.decode_utf8().ok().map(String::from)
+    } else if charset.eq_ignore_ascii_case("iso-8859-1") {
+        Some(bytes.map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+/// Make a name from an untrusted source safe to use as a single path component
+fn sanitize(name: &str) -> Option<String> {
+    let name: String = name
+        .chars()
+        .map(|c| if c.is_control() || RESERVED_CHARACTERS.contains(&c) { '_' } else { c })
+        .collect();
+    // Windows silently drops trailing dots and spaces
+    let name = name.trim_start().trim_end_matches(['.', ' ']);
+    if name.is_empty() {
+        return None;
+    }
+    let stem = name.split('.').next().unwrap_or(name);
+    if RESERVED_NAMES.iter().any(|reserved| reserved.eq_ignore_ascii_case(stem)) {
+        return Some(format!("_{name}"));
+    }
+    Some(name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn parameter(name: &str, value: &str) -> (String, String) {
+        (name.to_string(), value.to_string())
+    }
+
+    fn disposition(value: &'static str) -> HeaderMap {
+        HeaderMap::from_iter([(CONTENT_DISPOSITION, HeaderValue::from_static(value))])
+    }
+
+    #[test]
+    fn parameters_are_split_and_unquoted() {
+        assert_eq!(
+            parameters(r#"attachment; FileName = "a \"quoted\"; name.txt" ; size=10"#),
+            [parameter("filename", r#"a "quoted"; name.txt"#), parameter("size", "10")]
+        );
+        assert_eq!(
+            parameters("attachment;filename=plain.txt"),
+            [parameter("filename", "plain.txt")]
+        );
+        assert_eq!(parameters("inline"), []);
+    }
+
+    #[test]
+    fn extended_values_are_decoded() {
+        assert_eq!(
+            decode_extended("UTF-8''na%C3%AFve%20file.txt").as_deref(),
+            Some("naïve file.txt")
+        );
+        assert_eq!(decode_extended("utf-8'en'%E2%82%AC.txt").as_deref(), Some("€.txt"));
+        assert_eq!(decode_extended("iso-8859-1''caf%E9.txt").as_deref(), Some("café.txt"));
+        // Invalid UTF-8, unknown charsets and values without a charset are rejected
+        assert_eq!(decode_extended("UTF-8''caf%E9.txt"), None);
+        assert_eq!(decode_extended("Shift_JIS''file.txt"), None);
+        assert_eq!(decode_extended("file.txt"), None);
+    }
+
+    #[test]
+    fn names_are_sanitized() {
+        assert_eq!(sanitize("file.txt").as_deref(), Some("file.txt"));
+        assert_eq!(sanitize("../../etc/passwd").as_deref(), Some(".._.._etc_passwd"));
+        assert_eq!(sanitize("C:\\Windows\\file?.txt").as_deref(), Some("C__Windows_file_.txt"));
+        assert_eq!(sanitize("line\nbreak").as_deref(), Some("line_break"));
+        assert_eq!(sanitize(" name. . ").as_deref(), Some("name"));
+        assert_eq!(sanitize("con.txt").as_deref(), Some("_con.txt"));
+        assert_eq!(sanitize("LPT1").as_deref(), Some("_LPT1"));
+        assert_eq!(sanitize("console.txt").as_deref(), Some("console.txt"));
+        assert_eq!(sanitize(".."), None);
+        assert_eq!(sanitize("  "), None);
+    }
+
+    #[test]
+    fn extended_filename_is_preferred() {
+        let headers = disposition(r#"attachment; filename="fallback.txt"; filename*=UTF-8''%E2%82%AC%20rates.txt"#);
+        assert_eq!(from_content_disposition(&headers).as_deref(), Some("€ rates.txt"));
+        let headers = disposition(r#"attachment; filename*=Shift_JIS''file.txt; filename="fallback.txt""#);
+        assert_eq!(from_content_disposition(&headers).as_deref(), Some("fallback.txt"));
+    }
+
+    #[test]
+    fn url_is_used_without_a_header() {
+        let url = Url::parse("https://example.com/files/my%20file.tar.gz?download=1").unwrap();
+        assert_eq!(infer(&url, &HeaderMap::new()).as_deref(), Some("my file.tar.gz"));
+        assert_eq!(
+            infer(&url, &disposition("attachment; filename=other.zip")).as_deref(),
+            Some("other.zip")
+        );
+        let url = Url::parse("https://example.com/files/").unwrap();
+        assert_eq!(infer(&url, &HeaderMap::new()), None);
+    }
+}
@@ -0,0 +1,206 @@
+use super::verify::{Checksum, CsType};
+use reqwest::{
+    header::{HeaderMap, CONTENT_RANGE, ETAG},
+    Url,
+};
+
+/// Algorithms in order of preference when a server sends several digests
+const PREFERENCE: [CsType; 6] = [CsType::Sha512, CsType::Sha384, CsType::Sha256, CsType::Sha1, CsType::MD5, CsType::Crc32c];
+
+/// Determines whether downloads without a checksum are verified against digests sent by the server
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ServerChecksums {
+    #[default]
+    Ignore,
+    /// Use the `Repr-Digest`, `Digest`, `x-goog-hash` or `Content-MD5` headers, in that order
+    Headers,
+    /// Also fall back to ETags which look like MD5 hashes, as S3 sends for objects which weren't uploaded in parts.
+    /// Other servers may send ETags which look the same without being hashes, so this is only safe for S3 compatible storage.
+    HeadersAndETag,
+}
+
+/// Where the checksum a download was verified against came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChecksumSource {
+    /// Given through [`Download::with_checksum`](crate::Download::with_checksum)
+    Explicit,
+    /// Looked up in the checksum file at this URL
+    ChecksumFile(Url),
+    /// The RFC 9530 `Repr-Digest` header
+    ReprDigest,
+    /// The legacy RFC 3230 `Digest` header
+    Digest,
+    /// Google Cloud Storage's `x-goog-hash` header
+    GoogHash,
+    ContentMd5,
+    ETag,
+}
+
+/// Find the strongest digest of a remote file among its response headers
+pub(crate) fn from_headers(headers: &HeaderMap, policy: ServerChecksums) -> Option<(Checksum, ChecksumSource)> {
+    if policy == ServerChecksums::Ignore {
+        return None;
+    }
+    let values = |name: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(algorithm, digest)| (algorithm.trim(), digest.trim()))
+            .collect::<Vec<_>>()
+    };
+    // Repr-Digest is a structured field, with the base64 digest wrapped in colons
+    let repr_digest = values("repr-digest")
+        .into_iter()
+        .map(|(algorithm, digest)| (algorithm, digest.trim_matches(':')))
+        .collect();
+    let checksum = strongest(repr_digest)
+        .map(|checksum| (checksum, ChecksumSource::ReprDigest))
+        .or_else(|| strongest(values("digest")).map(|checksum| (checksum, ChecksumSource::Digest)))
+        .or_else(|| strongest(values("x-goog-hash")).map(|checksum| (checksum, ChecksumSource::GoogHash)))
+        // Content-MD5 covers the body of the response, which is only part of the file for a range request
+        .or_else(|| {
+            let digest = headers.get("content-md5")?.to_str().ok()?;
+            let checksum = Checksum::from_digest(digest, CsType::MD5).ok()?;
+            (!headers.contains_key(CONTENT_RANGE)).then_some((checksum, ChecksumSource::ContentMd5))
+        });
+    checksum.or_else(|| {
+        let etag = headers.get(ETAG)?.to_str().ok()?.trim_matches('"');
+        // Weak and multipart ETags aren't hashes of the file
+        let is_md5 = etag.len() == 32 && etag.bytes().all(|byte| byte.is_ascii_hexdigit());
+        let checksum = Checksum::from_digest(etag, CsType::MD5).ok()?;
+        (policy == ServerChecksums::HeadersAndETag && is_md5).then_some((checksum, ChecksumSource::ETag))
+    })
+}
+
+fn strongest(digests: Vec<(&str, &str)>) -> Option<Checksum> {
+    let mut digests: Vec<(CsType, &str)> = digests
+        .into_iter()
+        .filter_map(|(algorithm, digest)| {
+            // RFC 3230 names SHA-1 `SHA`
+            let checksum_type = match algorithm {
+                _ if algorithm.eq_ignore_ascii_case("sha") => CsType::Sha1,
+                _ => CsType::from_name(algorithm)?,
+            };
+            Some((checksum_type, digest))
+        })
+        .filter(|(checksum_type, _)| PREFERENCE.contains(checksum_type))
+        .collect();
+    digests.sort_by_key(|(checksum_type, _)| PREFERENCE.iter().position(|preferred| preferred == checksum_type));
+    digests
+        .into_iter()
+        .find_map(|(checksum_type, digest)| Checksum::from_digest(digest, checksum_type).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::downloader::verify::fixture::*;
+    use reqwest::header::{HeaderName, HeaderValue};
+
+    const SHA1_BASE64: &str = "9XLTlvrpIGYocU+yzgD3LpTyJY8=";
+    const MD5_BASE64: &str = "sZRqySSS0jR8YjW00mERhA==";
+    /// A well formed SHA-512 digest which doesn't match, to show when SHA-512 was preferred
+    const WRONG_SHA512: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA==";
+
+    fn header_map(pairs: &[(&'static str, String)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| (HeaderName::from_static(name), HeaderValue::from_str(value).unwrap()))
+            .collect()
+    }
+
+    /// The source of the digest found in `headers`, and whether it matches the fixture data
+    fn found(headers: &HeaderMap, policy: ServerChecksums) -> Option<(ChecksumSource, bool)> {
+        from_headers(headers, policy).map(|(checksum, source)| (source, verifies(checksum)))
+    }
+
+    #[test]
+    fn repr_digest_is_unwrapped() {
+        let headers = header_map(&[("repr-digest", format!("sha-256=:{SHA256_BASE64}:"))]);
+        assert_eq!(
+            found(&headers, ServerChecksums::Headers),
+            Some((ChecksumSource::ReprDigest, true))
+        );
+        assert_eq!(found(&headers, ServerChecksums::Ignore), None);
+    }
+
+    #[test]
+    fn strongest_digest_is_preferred() {
+        let headers = header_map(&[("repr-digest", format!("sha-256=:{SHA256_BASE64}:, sha-512=:{WRONG_SHA512}:"))]);
+        assert_eq!(
+            found(&headers, ServerChecksums::Headers),
+            Some((ChecksumSource::ReprDigest, false))
+        );
+        // Unknown algorithms and undecodable digests are passed over
+        let headers = header_map(&[(
+            "repr-digest",
+            format!("unknown=:abc:, sha-512=:abc:, sha-256=:{SHA256_BASE64}:"),
+        )]);
+        assert_eq!(
+            found(&headers, ServerChecksums::Headers),
+            Some((ChecksumSource::ReprDigest, true))
+        );
+    }
+
+    #[test]
+    fn digest_accepts_the_rfc_3230_sha_alias() {
+        let headers = header_map(&[("digest", format!("SHA={SHA1_BASE64}"))]);
+        assert_eq!(found(&headers, ServerChecksums::Headers), Some((ChecksumSource::Digest, true)));
+        let headers = header_map(&[("digest", format!("MD5={MD5_BASE64},SHA={SHA1_BASE64}"))]);
+        assert_eq!(found(&headers, ServerChecksums::Headers), Some((ChecksumSource::Digest, true)));
+    }
+
+    #[test]
+    fn goog_hash_lists_several_digests() {
+        let headers = header_map(&[("x-goog-hash", format!("crc32c=AAAAAA==,md5={MD5_BASE64}"))]);
+        // MD5 is preferred over CRC32C, which wouldn't match
+        assert_eq!(
+            found(&headers, ServerChecksums::Headers),
+            Some((ChecksumSource::GoogHash, true))
+        );
+        let headers = header_map(&[("x-goog-hash", "crc32c=AAAAAA==".into()), ("x-goog-hash", format!("md5={MD5_BASE64}"))]);
+        assert_eq!(
+            found(&headers, ServerChecksums::Headers),
+            Some((ChecksumSource::GoogHash, true))
+        );
+    }
+
+    #[test]
+    fn headers_are_used_in_order() {
+        let headers = header_map(&[
+            ("content-md5", MD5_BASE64.into()),
+            ("x-goog-hash", format!("md5={MD5_BASE64}")),
+            ("digest", format!("sha-512={WRONG_SHA512}")),
+        ]);
+        assert_eq!(found(&headers, ServerChecksums::Headers), Some((ChecksumSource::Digest, false)));
+    }
+
+    #[test]
+    fn content_md5_is_skipped_for_ranges() {
+        let full = header_map(&[("content-md5", MD5_BASE64.into())]);
+        assert_eq!(found(&full, ServerChecksums::Headers), Some((ChecksumSource::ContentMd5, true)));
+        let partial = header_map(&[("content-md5", MD5_BASE64.into()), ("content-range", "bytes 0-0/6".into())]);
+        assert_eq!(found(&partial, ServerChecksums::Headers), None);
+    }
+
+    #[test]
+    fn etags_are_only_used_when_allowed() {
+        let headers = header_map(&[("etag", format!("\"{MD5}\""))]);
+        assert_eq!(found(&headers, ServerChecksums::Headers), None);
+        assert_eq!(
+            found(&headers, ServerChecksums::HeadersAndETag),
+            Some((ChecksumSource::ETag, true))
+        );
+    }
+
+    #[test]
+    fn etags_which_arent_hashes_are_ignored() {
+        for etag in [format!("W/\"{MD5}\""), format!("\"{MD5}-2\""), "\"abc\"".into()] {
+            let headers = header_map(&[("etag", etag)]);
+            assert_eq!(found(&headers, ServerChecksums::HeadersAndETag), None);
+        }
+    }
+}
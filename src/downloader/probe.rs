@@ -0,0 +1,179 @@
+use super::events::DownloadId;
+use crate::error::DownloadError;
+use reqwest::{
+    header::{HeaderMap, ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, RANGE},
+    Response, StatusCode, Url,
+};
+use reqwest_middleware::{ClientWithMiddleware, RequestBuilder};
+
+/// Information about a remote file, gathered before it is downloaded
+pub(crate) struct Metadata {
+    /// The URL after any redirects have been followed
+    pub(crate) url: Url,
+    pub(crate) content_length: Option<u64>,
+    /// Whether the server has shown that it honors range requests
+    pub(crate) accepts_ranges: bool,
+    pub(crate) headers: HeaderMap,
+}
+
+impl Metadata {
+    fn new(response: Response, content_length: Option<u64>, accepts_ranges: bool) -> Self {
+        Self {
+            url: response.url().clone(),
+            content_length,
+            accepts_ranges,
+            headers: response.headers().clone(),
+        }
+    }
+}
+
+/// Fetch the metadata of a remote file without downloading its contents.
+/// A HEAD request is tried first, falling back to a GET request for only the first byte
+/// for servers which don't support HEAD, or don't report a length or range support in response to it.
+pub(crate) async fn probe(client: &ClientWithMiddleware, id: usize, url: &Url, headers: Option<&HeaderMap>) -> Result<Metadata, DownloadError> {
+    let with_headers = |request| with_headers(request, id, headers);
+    let head = with_headers(client.head(url.clone())).send().await;
+    match head {
+        Ok(response) if response.status().is_success() => {
+            let accept_ranges = response.headers().get(ACCEPT_RANGES).and_then(|value| value.to_str().ok());
+            match (header_length(response.headers()), accept_ranges) {
+                (Some(length), Some("bytes")) => return Ok(Metadata::new(response, Some(length), true)),
+                (Some(length), Some("none")) => return Ok(Metadata::new(response, Some(length), false)),
+                _ => log::debug!("HEAD response for {url} did not include a length or range support"),
+            }
+        }
+        Ok(response) => log::debug!("HEAD request for {url} failed with status {}", response.status()),
+        Err(e) => log::debug!("HEAD request for {url} failed: {e}"),
+    }
+
+    let request = with_headers(client.get(url.clone())).header(RANGE, "bytes=0-0");
+    let response = request.send().await.map_err(DownloadError::RequestError)?;
+    // There's no first byte to send of an empty file
+    if response.status() == StatusCode::RANGE_NOT_SATISFIABLE && ContentRange::complete_length(response.headers()) == Some(0) {
+        return Ok(Metadata::new(response, Some(0), false));
+    }
+    let response = response.error_for_status().map_err(DownloadError::ReqwestError)?;
+    let metadata = match (response.status(), ContentRange::from_headers(response.headers())) {
+        (StatusCode::PARTIAL_CONTENT, Some(ContentRange { start: 0, end: 0, total })) => Metadata::new(response, total, true),
+        (StatusCode::PARTIAL_CONTENT, _) => {
+            log::warn!("{url} responded with an unexpected range, treating it as not supporting ranges");
+            Metadata::new(response, None, false)
+        }
+        _ => {
+            let length = header_length(response.headers());
+            Metadata::new(response, length, false)
+        }
+    };
+    Ok(metadata)
+}
+
+/// Fetch the first bytes of a remote file, so that its contents can be identified before it's downloaded
+#[cfg(feature = "unarchive")]
+pub(crate) async fn sniff(client: &ClientWithMiddleware, id: usize, url: &Url, headers: Option<&HeaderMap>, length: usize) -> Result<Vec<u8>, DownloadError> {
+    let request = with_headers(client.get(url.clone()), id, headers).header(RANGE, format!("bytes=0-{}", length - 1));
+    let response = request.send().await.map_err(DownloadError::RequestError)?;
+    let mut response = response.error_for_status().map_err(DownloadError::ReqwestError)?;
+    // Servers which ignore the range send the whole file, so stop reading once there's enough
+    let mut data = Vec::with_capacity(length);
+    while data.len() < length {
+        match response.chunk().await.map_err(DownloadError::ReqwestError)? {
+            Some(chunk) => data.extend_from_slice(&chunk),
+            None => break,
+        }
+    }
+    data.truncate(length);
+    Ok(data)
+}
+
+fn with_headers(mut request: RequestBuilder, id: usize, headers: Option<&HeaderMap>) -> RequestBuilder {
+    request = request.with_extension(DownloadId(id));
+    if let Some(headers) = headers {
+        request = request.headers(headers.clone());
+    }
+    request
+}
+
+/// A parsed `Content-Range` header, e.g. `bytes 0-499/1234`
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct ContentRange {
+    pub(crate) start: u64,
+    /// The last byte included in the response
+    pub(crate) end: u64,
+    pub(crate) total: Option<u64>,
+}
+
+impl ContentRange {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let range = headers.get(CONTENT_RANGE)?.to_str().ok()?.strip_prefix("bytes ")?;
+        let (span, total) = range.split_once('/')?;
+        let (start, end) = span.split_once('-')?;
+        let total = match total.trim() {
+            "*" => None,
+            total => Some(total.parse().ok()?),
+        };
+        Some(Self {
+            start: start.trim().parse().ok()?,
+            end: end.trim().parse().ok()?,
+            total,
+        })
+    }
+    /// The length of the file given by the `bytes */1234` form, which is sent when a range can't be satisfied
+    pub(crate) fn complete_length(headers: &HeaderMap) -> Option<u64> {
+        let length = headers.get(CONTENT_RANGE)?.to_str().ok()?.strip_prefix("bytes */")?;
+        length.trim().parse().ok()
+    }
+}
+
+// Response::content_length reports the length of the body actually received, which is always 0 for HEAD requests
+fn header_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get(CONTENT_LENGTH)?.to_str().ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn parse(value: &'static str) -> Option<ContentRange> {
+        ContentRange::from_headers(&HeaderMap::from_iter([(CONTENT_RANGE, HeaderValue::from_static(value))]))
+    }
+
+    fn range(start: u64, end: u64, total: Option<u64>) -> Option<ContentRange> {
+        Some(ContentRange { start, end, total })
+    }
+
+    #[test]
+    fn ranges_are_parsed() {
+        assert_eq!(parse("bytes 0-499/1234"), range(0, 499, Some(1234)));
+        assert_eq!(parse("bytes 0-0/1"), range(0, 0, Some(1)));
+        assert_eq!(parse("bytes 500-1233/*"), range(500, 1233, None));
+        assert_eq!(parse("bytes 0 - 499 / 1234"), range(0, 499, Some(1234)));
+        assert_eq!(
+            parse("bytes 0-18446744073709551614/18446744073709551615"),
+            range(0, u64::MAX - 1, Some(u64::MAX))
+        );
+    }
+
+    #[test]
+    fn malformed_ranges_are_rejected() {
+        // The form sent with a 416 response, which has no range
+        assert_eq!(parse("bytes */1234"), None);
+        assert_eq!(parse("items 0-499/1234"), None);
+        assert_eq!(parse("0-499/1234"), None);
+        assert_eq!(parse("bytes 0-499"), None);
+        assert_eq!(parse("bytes 0-499/"), None);
+        assert_eq!(parse("bytes -499/1234"), None);
+        assert_eq!(parse("bytes a-b/c"), None);
+        assert_eq!(ContentRange::from_headers(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn complete_length_is_parsed() {
+        let headers = |value| HeaderMap::from_iter([(CONTENT_RANGE, HeaderValue::from_static(value))]);
+        assert_eq!(ContentRange::complete_length(&headers("bytes */0")), Some(0));
+        assert_eq!(ContentRange::complete_length(&headers("bytes */1234")), Some(1234));
+        assert_eq!(ContentRange::complete_length(&headers("bytes */*")), None);
+        assert_eq!(ContentRange::complete_length(&headers("bytes 0-0/1")), None);
+        assert_eq!(ContentRange::complete_length(&HeaderMap::new()), None);
+    }
+}
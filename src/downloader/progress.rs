@@ -0,0 +1,119 @@
+use super::events::{DownloadEvent, ProgressObserver};
+use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+
+pub struct Progress {
+    total: Option<ProgressStyle>,
+    individual: Option<ProgressStyle>,
+}
+impl Default for Progress {
+    fn default() -> Self {
+        Self::new().with_default_total().with_default_individual()
+    }
+}
+impl Progress {
+    const DEFAULT_TOTAL_PROGRESS: &'static str = "{elapsed_precise} {bar:30.cyan} {human_pos:>} / {human_len} ({percent}%)";
+    const DEFAULT_INDIVIDUAL_PROGRESS: &'static str = "{bar:30.blue/red} ({percent}%) {bytes:>12.green} / {total_bytes:<12.green} {bytes_per_sec:>13.blue} - ETA: {eta_precise}";
+    const PROGRESS_LINE: &'static str = "━╾╴─";
+
+    pub fn new() -> Self {
+        Self { total: None, individual: None }
+    }
+    pub fn with_default_total(mut self) -> Self {
+        self.total = Some(ProgressStyle::with_template(Progress::DEFAULT_TOTAL_PROGRESS).unwrap());
+        self
+    }
+    pub fn with_default_individual(mut self) -> Self {
+        self.individual = Some(
+            ProgressStyle::with_template(Progress::DEFAULT_INDIVIDUAL_PROGRESS)
+                .unwrap()
+                .progress_chars(Progress::PROGRESS_LINE),
+        );
+        self
+    }
+    pub fn with_total(mut self, style: ProgressStyle) -> Self {
+        self.total = Some(style);
+        self
+    }
+    pub fn with_individual(mut self, style: ProgressStyle) -> Self {
+        self.individual = Some(style);
+        self
+    }
+    fn is_enabled(&self) -> bool {
+        self.total.is_some() || self.individual.is_some()
+    }
+    pub(crate) fn renderer(&self, downloads: usize) -> Option<ProgressRenderer> {
+        if !self.is_enabled() {
+            return None;
+        }
+        let multi = MultiProgress::new();
+        let total = match (&self.total, downloads) {
+            (Some(style), 2..) => {
+                let progress = ProgressBar::new(downloads as u64).with_style(style.clone());
+                progress.enable_steady_tick(std::time::Duration::from_millis(100));
+                Some(multi.add(progress))
+            }
+            _ => None,
+        };
+        let individual = match &self.individual {
+            Some(individual_style) => (0..downloads)
+                .map(|_| {
+                    // The length is filled in once the download has started
+                    let progress = ProgressBar::new(0).with_style(individual_style.clone());
+                    progress.enable_steady_tick(std::time::Duration::from_millis(100));
+                    multi.add(progress)
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        Some(ProgressRenderer { _multi: multi, total, individual })
+    }
+}
+
+/// Draws progress bars in the terminal in response to download events
+pub(crate) struct ProgressRenderer {
+    _multi: MultiProgress,
+    total: Option<ProgressBar>,
+    individual: Vec<ProgressBar>,
+}
+
+impl ProgressRenderer {
+    pub(crate) fn finish(&self) {
+        if let Some(total) = &self.total {
+            total.finish();
+        }
+    }
+}
+
+impl ProgressObserver for ProgressRenderer {
+    fn on_event(&self, event: &DownloadEvent) {
+        match *event {
+            DownloadEvent::Started { id, content_length, downloaded } => {
+                if let Some(progress) = self.individual.get(id) {
+                    if let Some(length) = content_length {
+                        progress.set_length(length);
+                    }
+                    progress.set_position(downloaded);
+                }
+            }
+            DownloadEvent::Progress { id, bytes, .. } => {
+                if let Some(progress) = self.individual.get(id) {
+                    progress.inc(bytes);
+                }
+            }
+            DownloadEvent::Finished { id } => {
+                if let Some(progress) = self.individual.get(id) {
+                    progress.finish();
+                }
+                if let Some(total) = &self.total {
+                    total.inc(1);
+                }
+            }
+            DownloadEvent::Failed { id, .. } => {
+                if let Some(progress) = self.individual.get(id) {
+                    progress.abandon();
+                }
+            }
+            _ => (),
+        }
+    }
+}
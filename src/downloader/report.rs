@@ -0,0 +1,38 @@
+#[cfg(feature = "verification")]
+use super::integrity::ChecksumSource;
+use crate::error::DownloadError;
+use reqwest::Url;
+use std::{path::PathBuf, time::Duration};
+
+/// Determines how a [`Downloader`](crate::Downloader) reacts when one of its downloads fails
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ErrorPolicy {
+    /// Stop every other download as soon as one fails
+    FailFast,
+    /// Keep running the remaining downloads and report every failure
+    #[default]
+    Continue,
+}
+
+/// The outcome of a single download
+#[derive(Debug)]
+pub struct DownloadReport {
+    /// The URL the download was requested from
+    pub url: Url,
+    pub result: Result<DownloadSummary, DownloadError>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadSummary {
+    /// Where the file was saved, or the directory an archive was extracted into.
+    /// This is `None` when the download was written to a file supplied by the caller.
+    pub path: Option<PathBuf>,
+    /// The size of the downloaded file, before any decompression
+    pub bytes: u64,
+    pub duration: Duration,
+    /// Whether the download was skipped because its output file already existed
+    pub skipped: bool,
+    /// Where the checksum the download was verified against came from, if it was verified
+    #[cfg(feature = "verification")]
+    pub checksum_source: Option<ChecksumSource>,
+}
@@ -0,0 +1,110 @@
+use crate::error::DownloadError;
+use reqwest::{
+    header::{HeaderMap, HeaderName, ETAG, LAST_MODIFIED},
+    Url,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::File,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Self {
+        let header = |name: HeaderName| headers.get(name).and_then(|value| value.to_str().ok()).map(String::from);
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub(crate) struct ChunkState {
+    pub(crate) begin: u64,
+    pub(crate) position: u64,
+    pub(crate) end: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct ResumeState {
+    url: String,
+    content_length: u64,
+    validators: Validators,
+    pub(crate) chunks: Vec<ChunkState>,
+}
+
+impl ResumeState {
+    pub(crate) fn new(url: &Url, content_length: u64, validators: Validators) -> Self {
+        Self {
+            url: url.to_string(),
+            content_length,
+            validators,
+            chunks: Vec::new(),
+        }
+    }
+    /// Load the state left behind by an interrupted download, if there is one
+    pub(crate) fn load(path: &Path) -> Result<Option<Self>, DownloadError> {
+        match File::open(path) {
+            Ok(file) => serde_json::from_reader(file)
+                .map(Some)
+                .map_err(|_| DownloadError::InvalidResumeState),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(DownloadError::FileError(e)),
+        }
+    }
+    pub(crate) fn matches_url(&self, url: &Url) -> bool {
+        self.url == url.as_str()
+    }
+    /// Whether the remote file is unchanged since the partial data was written
+    pub(crate) fn matches_remote(&self, content_length: Option<u64>, validators: &Validators) -> bool {
+        Some(self.content_length) == content_length && &self.validators == validators
+    }
+    /// Whether the partial file still holds everything the chunks have written to it
+    pub(crate) fn matches_partial(&self, path: &Path) -> bool {
+        let written = self
+            .chunks
+            .iter()
+            .filter(|chunk| chunk.position > chunk.begin)
+            .map(|chunk| chunk.position)
+            .max()
+            .unwrap_or(0);
+        path.metadata().is_ok_and(|metadata| metadata.len() >= written)
+    }
+}
+
+/// A state file which is kept up to date as chunks are written to disk
+pub(crate) struct StateFile {
+    path: PathBuf,
+    state: Mutex<ResumeState>,
+}
+
+impl StateFile {
+    pub(crate) fn create(path: PathBuf, state: ResumeState) -> Result<Self, DownloadError> {
+        let state_file = Self { path, state: Mutex::new(state) };
+        state_file.write(&state_file.state.lock().unwrap())?;
+        Ok(state_file)
+    }
+    pub(crate) fn update(&self, index: usize, position: u64) -> Result<(), DownloadError> {
+        let mut state = self.state.lock().unwrap();
+        state.chunks[index].position = position;
+        self.write(&state)
+    }
+    pub(crate) fn remove(self) -> Result<(), DownloadError> {
+        std::fs::remove_file(self.path).map_err(DownloadError::FileError)
+    }
+    fn write(&self, state: &ResumeState) -> Result<(), DownloadError> {
+        // Write to a temporary file first so an interruption can never leave a truncated state file behind
+        let temp_path = self.path.with_extension("state.tmp");
+        let data = serde_json::to_vec(state).map_err(|_| DownloadError::InvalidResumeState)?;
+        std::fs::write(&temp_path, data).map_err(DownloadError::FileError)?;
+        std::fs::rename(temp_path, &self.path).map_err(DownloadError::FileError)
+    }
+}
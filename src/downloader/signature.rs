@@ -0,0 +1,278 @@
+use super::events::DownloadId;
+use crate::error::{DownloadError, SignatureError};
+use reqwest::{header::HeaderMap, Url};
+use reqwest_middleware::ClientWithMiddleware;
+use std::{io::Read, sync::Arc};
+#[cfg(feature = "gpgv")]
+use {
+    base64::{engine::general_purpose::STANDARD, Engine},
+    std::{io::Write, process::Stdio},
+    tempfile::NamedTempFile,
+    tokio::io::AsyncWriteExt,
+};
+
+/// A trusted key which signatures are checked against
+#[derive(Clone)]
+pub struct PublicKey(Key);
+
+#[derive(Clone)]
+enum Key {
+    Minisign(minisign_verify::PublicKey),
+    #[cfg(feature = "gpgv")]
+    OpenPgp(Arc<[u8]>),
+}
+
+impl PublicKey {
+    /// Parse a minisign public key, either the base64 key itself or the contents of a `.pub` file
+    pub fn minisign(key: &str) -> Result<Self, SignatureError> {
+        let key = key.trim();
+        let key = match key.lines().count() {
+            1 => minisign_verify::PublicKey::from_base64(key),
+            _ => minisign_verify::PublicKey::decode(key),
+        };
+        key.map(|key| Self(Key::Minisign(key))).map_err(|_| SignatureError::InvalidKey)
+    }
+    /// Use an OpenPGP public key or keyring, either binary or ASCII armored.
+    /// OpenPGP signatures are checked by running `gpgv`, which must be installed, so this needs the `gpgv` feature.
+    #[cfg(feature = "gpgv")]
+    pub fn openpgp(key: impl AsRef<[u8]>) -> Result<Self, SignatureError> {
+        let keyring = dearmor(key.as_ref()).ok_or(SignatureError::InvalidKey)?;
+        Ok(Self(Key::OpenPgp(keyring.into())))
+    }
+}
+
+#[derive(Clone)]
+enum Source {
+    Url(Url),
+    Bytes(Arc<[u8]>),
+}
+
+/// A detached signature, along with the key it must have been made with
+#[derive(Clone)]
+pub struct Signature {
+    key: PublicKey,
+    source: Source,
+    checksum_file: bool,
+}
+
+impl Signature {
+    /// Fetch the signature, such as a `.minisig`, `.sig` or `.asc` file, before the download starts
+    pub fn from_url(key: PublicKey, url: Url) -> Self {
+        Self {
+            key,
+            source: Source::Url(url),
+            checksum_file: false,
+        }
+    }
+    pub fn from_bytes(key: PublicKey, signature: impl AsRef<[u8]>) -> Self {
+        Self {
+            key,
+            source: Source::Bytes(signature.as_ref().into()),
+            checksum_file: false,
+        }
+    }
+    /// Check the signature against the checksum file given through [`Download::with_checksum_url`](crate::Download::with_checksum_url)
+    /// rather than the download itself, which is then verified against its checksum from the signed file.
+    /// Downloads with a checksum given through [`Download::with_checksum`](crate::Download::with_checksum) never use the checksum file,
+    /// so they fail rather than skipping the signature.
+    pub fn with_checksum_file(mut self, checksum_file: bool) -> Self {
+        self.checksum_file = checksum_file;
+        self
+    }
+    pub(crate) fn is_for_checksum_file(&self) -> bool {
+        self.checksum_file
+    }
+    /// Download the signature with the download's headers if it's hosted remotely, so that a missing signature fails the download before it starts
+    pub(crate) async fn fetch(&mut self, client: &ClientWithMiddleware, id: usize, headers: Option<&HeaderMap>) -> Result<(), DownloadError> {
+        if let Source::Url(url) = &self.source {
+            log::debug!("Fetching signature {url}");
+            let mut request = client.get(url.clone()).with_extension(DownloadId(id));
+            if let Some(headers) = headers {
+                request = request.headers(headers.clone());
+            }
+            let response = request.send().await.map_err(DownloadError::RequestError)?;
+            let response = response.error_for_status().map_err(DownloadError::ReqwestError)?;
+            let signature = response.bytes().await.map_err(DownloadError::ReqwestError)?;
+            self.source = Source::Bytes(signature.as_ref().into());
+        }
+        Ok(())
+    }
+    /// Check that the signature was made over `data` by the trusted key
+    pub(crate) async fn verify(&self, mut data: impl Read, buffer_size: usize) -> Result<(), SignatureError> {
+        let Source::Bytes(signature) = &self.source else {
+            unreachable!("Signatures are fetched before they're verified");
+        };
+        match &self.key.0 {
+            Key::Minisign(key) => {
+                let signature = std::str::from_utf8(signature).map_err(|_| SignatureError::InvalidSignature)?;
+                let signature = minisign_verify::Signature::decode(signature).map_err(|_| SignatureError::InvalidSignature)?;
+                let verified = match key.verify_stream(&signature) {
+                    Ok(mut verifier) => {
+                        let mut buf = vec![0; buffer_size];
+                        loop {
+                            match data.read(&mut buf)? {
+                                0 => break,
+                                read => verifier.update(&buf[..read]),
+                            }
+                        }
+                        verifier.finalize()
+                    }
+                    // Signatures made by old versions of minisign cover the data itself rather than its hash
+                    Err(minisign_verify::Error::UnsupportedLegacyMode) => {
+                        let mut contents = Vec::new();
+                        data.read_to_end(&mut contents)?;
+                        key.verify(&contents, &signature, true)
+                    }
+                    Err(e) => Err(e),
+                };
+                verified.map_err(|e| {
+                    log::error!("minisign verification failed: {e}");
+                    SignatureError::VerificationFailure
+                })
+            }
+            #[cfg(feature = "gpgv")]
+            Key::OpenPgp(keyring) => gpgv(keyring, signature, data, buffer_size).await,
+        }
+    }
+}
+
+/// Verify an OpenPGP signature by streaming the data into `gpgv`, which only succeeds for a good signature by a key in the keyring
+#[cfg(feature = "gpgv")]
+async fn gpgv(keyring: &[u8], signature: &[u8], mut data: impl Read, buffer_size: usize) -> Result<(), SignatureError> {
+    let keyring_file = temp_file(".gpg", keyring)?;
+    let signature_file = temp_file(".sig", signature)?;
+    let mut gpgv = tokio::process::Command::new("gpgv")
+        .arg("--keyring")
+        .arg(keyring_file.path())
+        .arg(signature_file.path())
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true)
+        .spawn()
+        .map_err(SignatureError::Gpgv)?;
+    let mut stdin = gpgv.stdin.take().unwrap();
+    let copy = async move {
+        let mut buf = vec![0; buffer_size];
+        loop {
+            match data.read(&mut buf)? {
+                0 => return Ok(()),
+                read => stdin.write_all(&buf[..read]).await?,
+            }
+        }
+    };
+    // Stdin is closed once everything has been written, while gpgv's output is collected at the same time
+    let (copied, output): (std::io::Result<()>, _) = futures::future::join(copy, gpgv.wait_with_output()).await;
+    let output = output.map_err(SignatureError::Gpgv)?;
+    log::debug!("gpgv: {}", String::from_utf8_lossy(&output.stderr).trim());
+    // gpgv stops reading early when it can't use the signature, which breaks the pipe
+    match copied {
+        Err(e) if e.kind() != std::io::ErrorKind::BrokenPipe => return Err(e.into()),
+        _ => (),
+    }
+    if !output.status.success() {
+        return Err(SignatureError::VerificationFailure);
+    }
+    Ok(())
+}
+
+/// Strip the ASCII armor from OpenPGP data, since `gpgv` only reads binary keyrings. Binary data is returned unchanged.
+#[cfg(feature = "gpgv")]
+fn dearmor(data: &[u8]) -> Option<Vec<u8>> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN PGP") => text,
+        _ => return Some(data.to_vec()),
+    };
+    let mut binary = Vec::new();
+    let mut lines = text.lines().map(str::trim);
+    while lines.any(|line| line.starts_with("-----BEGIN PGP")) {
+        // Armor headers are separated from the data by an empty line
+        lines.find(|line| line.is_empty())?;
+        let body: String = lines
+            .by_ref()
+            .take_while(|line| !line.starts_with("-----END PGP"))
+            // The CRC is on its own line, starting with an equals sign
+            .filter(|line| !line.starts_with('='))
+            .collect();
+        binary.extend(STANDARD.decode(body).ok()?);
+    }
+    Some(binary)
+}
+
+/// Write data for `gpgv` to a file with a random name, readable only by the current user, which is removed when dropped
+#[cfg(feature = "gpgv")]
+fn temp_file(suffix: &str, contents: &[u8]) -> Result<NamedTempFile, SignatureError> {
+    let mut file = tempfile::Builder::new().prefix("quick_fetcher-").suffix(suffix).tempfile()?;
+    file.write_all(contents)?;
+    file.flush()?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::executor::block_on;
+
+    // Test vectors from minisign-verify, signing the data `test`
+    const KEY: &str = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO3";
+    const SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RUQf6LRCGA9i559r3g7V1qNyJDApGip8MfqcadIgT9CuhV3EMhHoN1mGTkUidF/z7SrlQgXdy8ofjb7bNJJylDOocrCo8KLzZwo=
+trusted comment: timestamp:1556193335\tfile:test
+y/rUw2y8/hOUYjZU71eHp/Wo1KZ40fGy2VJEDl34XMJM+TX48Ss/17u3IvIfbVR1FkZZSNCisQbuQY+bHwhEBg==";
+    const LEGACY_SIGNATURE: &str = "untrusted comment: signature from minisign secret key
+RWQf6LRCGA9i59SLOFxz6NxvASXDJeRtuZykwQepbDEGt87ig1BNpWaVWuNrm73YiIiJbq71Wi+dP9eKL8OC351vwIasSSbXxwA=
+trusted comment: timestamp:1555779966\tfile:test
+QtKMXWyYcwdpZAlPF7tE2ENJkRd1ujvKjlj1m9RtHTBnZPa5WKU5uWRs5GoP5M/VqE81QFuMKI5k/SfNQUaOAA==";
+
+    fn verify(key: &str, signature: &str, data: &[u8]) -> Result<(), SignatureError> {
+        let signature = Signature::from_bytes(PublicKey::minisign(key)?, signature);
+        // A tiny buffer makes sure the data is streamed in pieces
+        block_on(signature.verify(data, 1))
+    }
+
+    #[test]
+    fn minisign_keys_are_parsed() {
+        assert!(PublicKey::minisign(KEY).is_ok());
+        assert!(PublicKey::minisign(&format!("untrusted comment: minisign public key E7620F1842B4E81F\n{KEY}\n")).is_ok());
+        assert!(matches!(PublicKey::minisign("not a key"), Err(SignatureError::InvalidKey)));
+    }
+
+    #[test]
+    fn minisign_signatures_are_verified() {
+        verify(KEY, SIGNATURE, b"test").unwrap();
+        assert!(matches!(
+            verify(KEY, SIGNATURE, b"Test"),
+            Err(SignatureError::VerificationFailure)
+        ));
+        assert!(matches!(
+            verify(KEY, "not a signature", b"test"),
+            Err(SignatureError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn legacy_minisign_signatures_are_verified() {
+        verify(KEY, LEGACY_SIGNATURE, b"test").unwrap();
+        assert!(matches!(
+            verify(KEY, LEGACY_SIGNATURE, b"Test"),
+            Err(SignatureError::VerificationFailure)
+        ));
+    }
+
+    #[test]
+    fn signatures_by_other_keys_fail() {
+        let other_key = "RWQf6LRCGA9i53mlYecO4IzT51TGPpvWucNSCh1CBM0QTaLn73Y7GFO4";
+        assert!(matches!(
+            verify(other_key, SIGNATURE, b"test"),
+            Err(SignatureError::VerificationFailure)
+        ));
+    }
+
+    #[test]
+    fn checksum_file_signatures_are_marked() {
+        let signature = Signature::from_bytes(PublicKey::minisign(KEY).unwrap(), SIGNATURE);
+        assert!(!signature.is_for_checksum_file());
+        assert!(signature.with_checksum_file(true).is_for_checksum_file());
+    }
+}
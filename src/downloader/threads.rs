@@ -1,12 +1,17 @@
+#[cfg(feature = "signatures")]
+use crate::downloader::signature::Signature;
 #[cfg(feature = "verification")]
 use crate::downloader::verify::Checksum;
 
-#[cfg(feature = "unarchive")]
-use super::decompress::ArchiveFormat;
+use super::{
+    events::{DownloadEvent, DownloadId, Events},
+    probe::ContentRange,
+    resume::{ChunkState, StateFile},
+};
 use crate::error::DownloadError;
 use reqwest::{
     header::{HeaderMap, RANGE},
-    Url,
+    StatusCode, Url,
 };
 use reqwest_middleware::ClientWithMiddleware;
 use std::{
@@ -15,109 +20,174 @@ use std::{
     io::{Seek, SeekFrom, Write},
     sync::Arc,
 };
+#[cfg(feature = "verification")]
+use std::{io::Read, sync::Mutex};
 
 pub struct Chunks {
     chunks: Vec<Chunk>,
+    output: File,
+    #[cfg(feature = "signatures")]
+    length: u64,
+    #[cfg(feature = "verification")]
+    buffer_size: usize,
+    #[cfg(feature = "verification")]
+    hasher: Option<Arc<InOrderHasher>>,
 }
 
 impl Chunks {
-    pub(crate) fn new(threads: u8, length: u64) -> Self {
+    pub(crate) fn new(threads: u8, length: Option<u64>, output: File, buffer_size: usize) -> Result<Self, DownloadError> {
+        if threads == 0 {
+            return Err(DownloadError::InvalidThreads);
+        }
+        // A single chunk spanning an unbounded length reads until the server ends the stream
+        let length = length.unwrap_or(u64::MAX);
         let t = threads as u64;
-        let size = (length + t) / t;
-        let chunks = (0..threads)
+        let size = (length / t).saturating_add(1);
+        let states = (0..threads)
             .map(|t| {
-                let begin = size * t as u64;
-                let end = min(begin + size, length);
+                let begin = min(size * t as u64, length);
+                let end = min(begin.saturating_add(size), length);
                 log::info!("Chunk: {}-{}, t: {t}, length: {length}", begin, end);
-                Chunk { buf: Vec::new(), begin, end, length }
+                ChunkState { begin, position: begin, end }
             })
-            .collect::<Vec<Chunk>>();
-        Self { chunks }
+            .collect::<Vec<ChunkState>>();
+        Self::from_states(&states, length, output, buffer_size)
+    }
+    pub(crate) fn from_states(states: &[ChunkState], length: u64, output: File, buffer_size: usize) -> Result<Self, DownloadError> {
+        let chunks = states
+            .iter()
+            .enumerate()
+            .map(|(index, state)| {
+                let output = output.try_clone().map_err(DownloadError::FileError)?;
+                Ok(Chunk {
+                    output,
+                    buf: Vec::with_capacity(buffer_size),
+                    buffer_size,
+                    index,
+                    position: state.position,
+                    begin: state.begin,
+                    end: state.end,
+                    length,
+                    #[cfg(feature = "verification")]
+                    hasher: None,
+                })
+            })
+            .collect::<Result<Vec<Chunk>, DownloadError>>()?;
+        Ok(Self {
+            chunks,
+            output,
+            #[cfg(feature = "signatures")]
+            length,
+            #[cfg(feature = "verification")]
+            buffer_size,
+            #[cfg(feature = "verification")]
+            hasher: None,
+        })
+    }
+    /// The total amount of data written to the output so far
+    pub(crate) fn downloaded(&self) -> u64 {
+        self.chunks.iter().map(|chunk| chunk.position - chunk.begin).sum()
+    }
+    pub(crate) fn states(&self) -> Vec<ChunkState> {
+        self.chunks
+            .iter()
+            .map(|chunk| ChunkState {
+                begin: chunk.begin,
+                position: chunk.position,
+                end: chunk.end,
+            })
+            .collect()
     }
     pub(crate) async fn download(
         &mut self,
         client: &ClientWithMiddleware,
+        id: usize,
         url: Arc<Url>,
         headers: Option<Arc<HeaderMap>>,
-        #[cfg(feature = "render_progress")] progress: Option<indicatif::ProgressBar>,
+        state: Option<&StateFile>,
+        events: &Events,
     ) -> Result<(), DownloadError> {
         let futures = self.chunks.iter_mut().map(|chunk| {
             let headers = headers.clone();
-            chunk.download(
-                client,
-                (*url).clone(),
-                headers,
-                #[cfg(feature = "render_progress")]
-                progress.clone(),
-            )
+            chunk.download(client, id, (*url).clone(), headers, state, events)
         });
         futures::future::join_all(futures)
             .await
             .into_iter()
             .collect::<Result<Vec<_>, DownloadError>>()?;
-        #[cfg(feature = "render_progress")]
-        if let Some(progress) = progress {
-            progress.finish();
-        }
-        self.chunks.sort_by_key(|chunk| chunk.begin);
         Ok(())
     }
-    pub(crate) fn save(self, output: File) -> Result<(), DownloadError> {
-        for chunk in self.chunks {
-            let output = output.try_clone().map_err(DownloadError::FileError)?;
-            chunk.save(output)?;
+    pub(crate) fn save(self) -> Result<File, DownloadError> {
+        self.output.sync_all().map_err(DownloadError::FileError)?;
+        Ok(self.output)
+    }
+    /// Hash the output while it's downloaded, so that it can be verified against `checksums` without another pass over the file
+    #[cfg(feature = "verification")]
+    pub(crate) fn hash_with(&mut self, checksums: Vec<Checksum>) -> Result<(), DownloadError> {
+        let hasher = Arc::new(InOrderHasher(Mutex::new(HashState {
+            checksums,
+            hashed: 0,
+            written: self.chunks.iter().map(|chunk| (chunk.begin, chunk.position)).collect(),
+            output: self.output.try_clone().map_err(DownloadError::FileError)?,
+            buffer_size: self.buffer_size,
+        })));
+        for chunk in &mut self.chunks {
+            chunk.hasher = Some(hasher.clone());
         }
-        output.sync_all().map_err(DownloadError::FileError)?;
+        self.hasher = Some(hasher);
         Ok(())
     }
-    #[cfg(feature = "unarchive")]
-    pub(crate) fn save_archive(self, path: Option<std::path::PathBuf>, output: File, archive_format: ArchiveFormat) -> Result<(), crate::error::ArchiveError> {
-        let mut data = self
-            .chunks
-            .iter()
-            .map(|chunk| {
-                let end = (chunk.end - chunk.begin) as usize;
-                &chunk.buf[0..end]
-            })
-            .collect::<Vec<&[u8]>>();
-        archive_format.decompress(output, path, &mut data)
-    }
     #[cfg(feature = "verification")]
-    pub(crate) fn verify(&self, mut checksum: Checksum) -> Result<(), DownloadError> {
-        self.chunks.iter().for_each(|chunk| {
-            let range = 0..chunk.end as usize - chunk.begin as usize;
-            checksum.update(&chunk.buf[range]);
-        });
-        if checksum.verify() {
+    pub(crate) fn verify(&self) -> Result<(), DownloadError> {
+        let Some(hasher) = &self.hasher else {
+            return Ok(());
+        };
+        let mut state = hasher.0.lock().unwrap();
+        // Anything that couldn't be hashed during the download, such as data from a previous run, is read back now
+        state.catch_up().map_err(DownloadError::FileError)?;
+        if state.checksums.iter().cloned().all(Checksum::verify) {
             Ok(())
         } else {
             Err(DownloadError::InvalidChecksum)
         }
     }
+    #[cfg(feature = "signatures")]
+    pub(crate) async fn verify_signature(&self, signature: &Signature) -> Result<(), DownloadError> {
+        #[cfg(not(feature = "verification"))]
+        use std::io::Read;
+        let mut output = &self.output;
+        output.seek(SeekFrom::Start(0)).map_err(DownloadError::FileError)?;
+        signature.verify(output.take(self.length), self.buffer_size).await?;
+        Ok(())
+    }
 }
 
 pub struct Chunk {
+    output: File,
     buf: Vec<u8>,
+    buffer_size: usize,
+    index: usize,
+    position: u64,
     begin: u64,
     end: u64,
     length: u64,
+    #[cfg(feature = "verification")]
+    hasher: Option<Arc<InOrderHasher>>,
 }
 
 impl Chunk {
-    async fn download(
-        &mut self,
-        client: &ClientWithMiddleware,
-        url: Url,
-        headers: Option<Arc<HeaderMap>>,
-        #[cfg(feature = "render_progress")] progress: Option<indicatif::ProgressBar>,
-    ) -> Result<(), DownloadError> {
-        let mut response = client.get(url);
+    async fn download(&mut self, client: &ClientWithMiddleware, id: usize, url: Url, headers: Option<Arc<HeaderMap>>, state: Option<&StateFile>, events: &Events) -> Result<(), DownloadError> {
+        if self.position == self.end && self.length != 0 {
+            return Ok(());
+        }
+        let mut response = client.get(url).with_extension(DownloadId(id));
 
-        let range = match (self.begin, self.end, self.length) {
+        let range = match (self.position, self.end, self.length) {
             (0, end, length) if end == length => None,
-            (_, end, length) if end == length => Some(format!("bytes={}-", self.begin)),
-            _ => Some(format!("bytes={}-{}", self.begin, self.end)),
+            (_, end, length) if end == length => Some(format!("bytes={}-", self.position)),
+            _ => Some(format!("bytes={}-{}", self.position, self.end - 1)),
         };
+        let ranged = range.is_some();
         if let Some(range) = range {
             response = response.header(RANGE, range);
         }
@@ -125,26 +195,240 @@ impl Chunk {
             response = response.headers((*headers).clone());
         }
         let response = response.send().await.map_err(DownloadError::RequestError)?;
+        let response = response.error_for_status().map_err(DownloadError::ReqwestError)?;
+        if ranged {
+            self.check_range(&response)?;
+        }
         let mut stream = response.bytes_stream();
         while let Some(chunk) = futures::StreamExt::next(&mut stream)
             .await
             .transpose()
             .map_err(DownloadError::ReqwestError)?
         {
-            self.buf.extend_from_slice(&chunk);
-            #[cfg(feature = "render_progress")]
-            if let Some(ref progress) = progress {
-                progress.inc(chunk.len() as u64);
+            // Never write past the end of this chunk, even if the server sends more than was requested
+            let remaining = (self.end - self.position) as usize - self.buf.len();
+            let data = &chunk[..min(chunk.len(), remaining)];
+            self.buf.extend_from_slice(data);
+            if self.buf.len() >= self.buffer_size {
+                self.flush(state)?;
             }
+            events.emit(DownloadEvent::Progress {
+                id,
+                chunk: self.index,
+                bytes: data.len() as u64,
+            });
+        }
+        self.flush(state)
+    }
+    /// Ensure the server responded with exactly the span of data that was requested,
+    /// rather than ignoring the range and sending the whole file
+    fn check_range(&self, response: &reqwest::Response) -> Result<(), DownloadError> {
+        let expected = ContentRange {
+            start: self.position,
+            end: self.end - 1,
+            total: Some(self.length),
+        };
+        let content_range = ContentRange::from_headers(response.headers());
+        if response.status() != StatusCode::PARTIAL_CONTENT || content_range.as_ref() != Some(&expected) {
+            log::error!(
+                "Requested {expected:?}, server responded with {} and {content_range:?}",
+                response.status()
+            );
+            return Err(DownloadError::RangeMismatch);
         }
         Ok(())
     }
-    fn save(self, mut output: File) -> Result<(), DownloadError> {
-        log::debug!("Buf: {}, intended: {}", self.buf.len(), self.end - self.begin);
-        let pos = output.seek(SeekFrom::Start(self.begin)).map_err(DownloadError::FileError)?;
-        log::debug!("Seeked to {}, {}", self.begin, pos);
-        output.write_all(self.buf.as_slice()).map_err(DownloadError::FileError)?;
-        log::debug!("Wrote to {}", self.end);
+    fn flush(&mut self, state: Option<&StateFile>) -> Result<(), DownloadError> {
+        if self.buf.is_empty() {
+            return Ok(());
+        }
+        // Seeking and writing happen without yielding, so chunks sharing the file descriptor can't interleave
+        self.output
+            .seek(SeekFrom::Start(self.position))
+            .map_err(DownloadError::FileError)?;
+        self.output.write_all(&self.buf).map_err(DownloadError::FileError)?;
+        log::debug!("Wrote {}-{}", self.position, self.position + self.buf.len() as u64);
+        #[cfg(feature = "verification")]
+        if let Some(hasher) = &self.hasher {
+            hasher.written(self.index, self.position, &self.buf)?;
+        }
+        self.position += self.buf.len() as u64;
+        self.buf.clear();
+        if let Some(state) = state {
+            state.update(self.index, self.position)?;
+        }
         Ok(())
     }
 }
+
+/// Hashes the output in order as it's downloaded. Data written right after everything hashed so far is hashed
+/// straight from the buffer, while chunks further into the file are read back once all data before them has been hashed.
+#[cfg(feature = "verification")]
+struct InOrderHasher(Mutex<HashState>);
+
+#[cfg(feature = "verification")]
+struct HashState {
+    checksums: Vec<Checksum>,
+    hashed: u64,
+    /// The beginning and current position of each chunk
+    written: Vec<(u64, u64)>,
+    output: File,
+    buffer_size: usize,
+}
+
+#[cfg(feature = "verification")]
+impl InOrderHasher {
+    /// Record that a chunk wrote `data` at `position`, hashing everything that's now contiguous with the hashed data
+    fn written(&self, index: usize, position: u64, data: &[u8]) -> Result<(), DownloadError> {
+        let mut state = self.0.lock().unwrap();
+        state.written[index].1 = position + data.len() as u64;
+        if state.hashed == position {
+            state.update(data);
+            state.hashed += data.len() as u64;
+        }
+        state.catch_up().map_err(DownloadError::FileError)
+    }
+}
+
+#[cfg(feature = "verification")]
+impl HashState {
+    fn update(&mut self, data: &[u8]) {
+        self.checksums.iter_mut().for_each(|checksum| checksum.update(data));
+    }
+    /// Read back and hash the data which has been written directly after the hashed data
+    fn catch_up(&mut self) -> std::io::Result<()> {
+        let mut buf = Vec::new();
+        while let Some(&(_, position)) = self
+            .written
+            .iter()
+            .find(|&&(begin, position)| begin <= self.hashed && self.hashed < position)
+        {
+            // Like writes, seeking and reading happen without yielding so that they can't interleave with a chunk's writes
+            self.output.seek(SeekFrom::Start(self.hashed))?;
+            buf.resize(self.buffer_size, 0);
+            let mut reader = (&self.output).take(position - self.hashed);
+            loop {
+                match reader.read(&mut buf)? {
+                    0 => break,
+                    read => {
+                        self.checksums.iter_mut().for_each(|checksum| checksum.update(&buf[..read]));
+                        self.hashed += read as u64;
+                    }
+                }
+            }
+            if self.hashed != position {
+                return Err(std::io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "verification"))]
+mod tests {
+    use super::*;
+    use crate::downloader::verify::CsType;
+    use sha2::{Digest, Sha256};
+
+    const LENGTH: u64 = 100;
+    const BUFFER_SIZE: usize = 16;
+
+    fn data() -> Vec<u8> {
+        (0..LENGTH).map(|byte| byte as u8).collect()
+    }
+
+    fn checksum(data: &[u8]) -> Checksum {
+        Checksum::new_inner(format!("{:x}", Sha256::digest(data)), CsType::Sha256)
+    }
+
+    /// Chunks of 34, 34 and 32 bytes, which have already written up to `positions`
+    fn chunks_at(output: File, positions: [u64; 3]) -> Chunks {
+        let states: Vec<ChunkState> = [(0, 34), (34, 68), (68, LENGTH)]
+            .into_iter()
+            .zip(positions)
+            .map(|((begin, end), position)| ChunkState { begin, position, end })
+            .collect();
+        let mut chunks = Chunks::from_states(&states, LENGTH, output, BUFFER_SIZE).unwrap();
+        chunks.hash_with(vec![checksum(&data())]).unwrap();
+        chunks
+    }
+
+    /// Write the next `length` bytes of a chunk, as a flush during a download would
+    fn write(chunks: &mut Chunks, index: usize, length: usize) {
+        let chunk = &mut chunks.chunks[index];
+        let position = chunk.position as usize;
+        chunk.buf.extend_from_slice(&data()[position..position + length]);
+        chunk.flush(None).unwrap();
+    }
+
+    fn hashed(chunks: &Chunks) -> u64 {
+        chunks.hasher.as_ref().unwrap().0.lock().unwrap().hashed
+    }
+
+    #[test]
+    fn zero_threads_are_refused() {
+        let result = Chunks::new(0, Some(LENGTH), tempfile::tempfile().unwrap(), BUFFER_SIZE);
+        assert!(matches!(result, Err(DownloadError::InvalidThreads)));
+    }
+
+    #[test]
+    fn chunks_cover_the_whole_file() {
+        for threads in [1, 3, 7, 255] {
+            let states = Chunks::new(threads, Some(LENGTH), tempfile::tempfile().unwrap(), BUFFER_SIZE)
+                .unwrap()
+                .states();
+            assert_eq!(states.len(), threads as usize);
+            assert_eq!(states[0].begin, 0);
+            assert_eq!(states.last().unwrap().end, LENGTH);
+            assert!(states.windows(2).all(|pair| pair[0].end == pair[1].begin));
+            assert!(states.iter().all(|state| state.position == state.begin));
+        }
+    }
+
+    #[test]
+    fn chunks_finishing_out_of_order_are_hashed_in_order() {
+        let mut chunks = chunks_at(tempfile::tempfile().unwrap(), [0, 34, 68]);
+        write(&mut chunks, 2, 32);
+        write(&mut chunks, 1, 20);
+        assert_eq!(hashed(&chunks), 0);
+        write(&mut chunks, 0, 16);
+        assert_eq!(hashed(&chunks), 16);
+        write(&mut chunks, 0, 18);
+        // Everything chunk 1 has written so far is read back once chunk 0 has finished
+        assert_eq!(hashed(&chunks), 54);
+        write(&mut chunks, 1, 14);
+        assert_eq!(hashed(&chunks), LENGTH);
+        chunks.verify().unwrap();
+    }
+
+    #[test]
+    fn resumed_chunks_are_read_back() {
+        let mut output = tempfile::tempfile().unwrap();
+        output.write_all(&data()).unwrap();
+        let mut chunks = chunks_at(output, [20, 50, 68]);
+        write(&mut chunks, 2, 32);
+        write(&mut chunks, 1, 18);
+        write(&mut chunks, 0, 14);
+        assert_eq!(hashed(&chunks), LENGTH);
+        chunks.verify().unwrap();
+
+        // Data from a previous run which was damaged since is caught
+        let mut output = tempfile::tempfile().unwrap();
+        let mut damaged = data();
+        damaged[10] ^= 0xff;
+        output.write_all(&damaged).unwrap();
+        let mut chunks = chunks_at(output, [20, 34, 68]);
+        write(&mut chunks, 0, 14);
+        write(&mut chunks, 2, 32);
+        assert!(matches!(chunks.verify(), Err(DownloadError::InvalidChecksum)));
+    }
+
+    #[test]
+    fn finished_resumed_downloads_are_hashed_when_verified() {
+        let mut output = tempfile::tempfile().unwrap();
+        output.write_all(&data()).unwrap();
+        let chunks = chunks_at(output, [34, 68, LENGTH]);
+        assert_eq!(hashed(&chunks), 0);
+        chunks.verify().unwrap();
+    }
+}
@@ -1,32 +1,85 @@
 use crate::error::ChecksumError;
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use blake2::{Blake2b512, Blake2s256};
 use md5::Md5;
 use sha1::Sha1;
 use sha2::{Digest, Sha224, Sha256, Sha384, Sha512};
+use sha3::{Sha3_256, Sha3_512};
+use std::io::Read;
 
+const PADDING_INDIFFERENT: GeneralPurposeConfig = GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent);
+const BASE64_ENGINES: [GeneralPurpose; 2] = [
+    GeneralPurpose::new(&alphabet::STANDARD, PADDING_INDIFFERENT),
+    GeneralPurpose::new(&alphabet::URL_SAFE, PADDING_INDIFFERENT),
+];
+
+#[derive(Clone)]
 pub struct Checksum {
     hasher: Hasher,
-    contents: String,
+    expected: Vec<u8>,
 }
 
 impl Checksum {
+    /// Create a checksum using a specific algorithm. The hash may be written as hex in either case, or as base64.
+    /// A hash which can't be decoded never matches.
     pub fn new_inner(hash: impl Into<String>, checksum_type: CsType) -> Self {
+        let hash = hash.into();
+        let expected = decode(&hash, Some(checksum_type.length())).unwrap_or_else(|| {
+            log::warn!("Could not decode {checksum_type:?} checksum {hash}");
+            Vec::new()
+        });
         Self {
             hasher: checksum_type.into(),
-            contents: hash.into(),
+            expected,
         }
     }
+    /// Create a checksum from a hash written as hex or base64, an SRI string such as `sha256-<base64>`,
+    /// or a prefixed hash such as `sha256:<hex>`.
+    ///
+    /// Without a prefix, the algorithm is guessed from the length of the hash. Only MD5, SHA-1 and SHA-2 are guessed,
+    /// since other algorithms produce hashes of the same lengths; use a prefix or [`new_inner`](Self::new_inner) for those.
     pub fn new(hash: impl Into<String>) -> Result<Self, ChecksumError> {
         let hash = hash.into();
-        let checksum_type = match hash.len() {
-            32 => CsType::MD5,
-            40 => CsType::Sha1,
-            56 => CsType::Sha224,
-            64 => CsType::Sha256,
-            96 => CsType::Sha384,
-            128 => CsType::Sha512,
+        let hash = hash.trim();
+        let prefixed = match hash.split_once(':') {
+            Some((name, digest)) => Some((CsType::from_name(name).ok_or(ChecksumError::UnrecognizedType)?, digest)),
+            // Unprefixed URL-safe base64 can contain dashes too, so an unknown SRI prefix isn't an error
+            None => hash
+                .split_once('-')
+                .and_then(|(name, digest)| Some((CsType::from_name(name)?, digest))),
+        };
+        if let Some((checksum_type, digest)) = prefixed {
+            return Self::from_digest(digest, checksum_type);
+        }
+        let expected = decode(hash, None).ok_or(ChecksumError::InvalidEncoding)?;
+        let checksum_type = match expected.len() {
+            16 => CsType::MD5,
+            20 => CsType::Sha1,
+            28 => CsType::Sha224,
+            32 => CsType::Sha256,
+            48 => CsType::Sha384,
+            64 => CsType::Sha512,
             _ => return Err(ChecksumError::UnrecognizedSize),
         };
-        Ok(Self::new_inner(hash, checksum_type))
+        Ok(Self {
+            hasher: checksum_type.into(),
+            expected,
+        })
+    }
+    /// Create a checksum from a hex or base64 digest, which must be the right length for the algorithm
+    pub(crate) fn from_digest(digest: &str, checksum_type: CsType) -> Result<Self, ChecksumError> {
+        let expected = decode(digest, Some(checksum_type.length())).ok_or(ChecksumError::InvalidEncoding)?;
+        if expected.len() != checksum_type.length() {
+            return Err(ChecksumError::UnrecognizedSize);
+        }
+        Ok(Self {
+            hasher: checksum_type.into(),
+            expected,
+        })
     }
     pub fn update(&mut self, data: &[u8]) {
         match &mut self.hasher {
@@ -36,22 +89,51 @@ impl Checksum {
             Hasher::Sha256(hasher) => hasher.update(data),
             Hasher::Sha384(hasher) => hasher.update(data),
             Hasher::Sha512(hasher) => hasher.update(data),
+            Hasher::Sha3_256(hasher) => hasher.update(data),
+            Hasher::Sha3_512(hasher) => hasher.update(data),
+            Hasher::Blake2b(hasher) => hasher.update(data),
+            Hasher::Blake2s(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => {
+                hasher.update(data);
+            }
+            Hasher::Crc32(hasher) => hasher.update(data),
+            Hasher::Crc32c(crc) => *crc = crc32c::crc32c_append(*crc, data),
         }
     }
     pub fn verify(self) -> bool {
         let hash = match self.hasher {
-            Hasher::Md5(hasher) => format!("{:x}", hasher.finalize()),
-            Hasher::Sha1(hasher) => format!("{:x}", hasher.finalize()),
-            Hasher::Sha224(hasher) => format!("{:x}", hasher.finalize()),
-            Hasher::Sha256(hasher) => format!("{:x}", hasher.finalize()),
-            Hasher::Sha384(hasher) => format!("{:x}", hasher.finalize()),
-            Hasher::Sha512(hasher) => format!("{:x}", hasher.finalize()),
+            Hasher::Md5(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha1(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha224(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha384(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha3_256(hasher) => hasher.finalize().to_vec(),
+            Hasher::Sha3_512(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake2b(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake2s(hasher) => hasher.finalize().to_vec(),
+            Hasher::Blake3(hasher) => hasher.finalize().as_bytes().to_vec(),
+            Hasher::Crc32(hasher) => hasher.finalize().to_be_bytes().to_vec(),
+            Hasher::Crc32c(crc) => crc.to_be_bytes().to_vec(),
         };
-        log::debug!("Hash: {}, Contents: {}", hash, self.contents);
-        hash == self.contents
+        log::debug!("Hash: {}, Contents: {}", hex(&hash), hex(&self.expected));
+        constant_time_eq::constant_time_eq(&hash, &self.expected)
+    }
+}
+
+/// Hash everything read from `reader` in a single pass and check it against every checksum
+pub(crate) fn verify_reader(checksums: &[Checksum], mut reader: impl Read, buffer_size: usize) -> std::io::Result<bool> {
+    let mut checksums = checksums.to_vec();
+    let mut buf = vec![0; buffer_size];
+    loop {
+        match reader.read(&mut buf)? {
+            0 => return Ok(checksums.into_iter().all(Checksum::verify)),
+            read => checksums.iter_mut().for_each(|checksum| checksum.update(&buf[..read])),
+        }
     }
 }
 
+#[derive(Clone)]
 pub enum Hasher {
     Md5(Md5),
     Sha1(Sha1),
@@ -59,6 +141,13 @@ pub enum Hasher {
     Sha256(Sha256),
     Sha384(Sha384),
     Sha512(Sha512),
+    Sha3_256(Sha3_256),
+    Sha3_512(Sha3_512),
+    Blake2b(Blake2b512),
+    Blake2s(Blake2s256),
+    Blake3(Box<blake3::Hasher>),
+    Crc32(crc32fast::Hasher),
+    Crc32c(u32),
 }
 impl From<CsType> for Hasher {
     fn from(value: CsType) -> Self {
@@ -69,10 +158,53 @@ impl From<CsType> for Hasher {
             CsType::Sha256 => Self::Sha256(Sha256::new()),
             CsType::Sha384 => Self::Sha384(Sha384::new()),
             CsType::Sha512 => Self::Sha512(Sha512::new()),
+            CsType::Sha3_256 => Self::Sha3_256(Sha3_256::new()),
+            CsType::Sha3_512 => Self::Sha3_512(Sha3_512::new()),
+            CsType::Blake2b => Self::Blake2b(Blake2b512::new()),
+            CsType::Blake2s => Self::Blake2s(Blake2s256::new()),
+            CsType::Blake3 => Self::Blake3(Box::default()),
+            CsType::Crc32 => Self::Crc32(crc32fast::Hasher::new()),
+            CsType::Crc32c => Self::Crc32c(0),
         }
     }
 }
 
+impl CsType {
+    /// The length of the algorithm's digest in bytes
+    fn length(&self) -> usize {
+        match self {
+            Self::Crc32 | Self::Crc32c => 4,
+            Self::MD5 => 16,
+            Self::Sha1 => 20,
+            Self::Sha224 => 28,
+            Self::Sha256 | Self::Sha3_256 | Self::Blake2s | Self::Blake3 => 32,
+            Self::Sha384 => 48,
+            Self::Sha512 | Self::Sha3_512 | Self::Blake2b => 64,
+        }
+    }
+    /// Recognize the name of an algorithm as written in checksum files, e.g. `SHA256` or `sha-256`
+    pub(crate) fn from_name(name: &str) -> Option<Self> {
+        let name = name.trim().to_ascii_uppercase().replace('-', "");
+        Some(match name.as_str() {
+            "MD5" => Self::MD5,
+            "SHA1" => Self::Sha1,
+            "SHA224" => Self::Sha224,
+            "SHA256" => Self::Sha256,
+            "SHA384" => Self::Sha384,
+            "SHA512" => Self::Sha512,
+            "SHA3256" => Self::Sha3_256,
+            "SHA3512" => Self::Sha3_512,
+            "BLAKE2B" | "BLAKE2B512" => Self::Blake2b,
+            "BLAKE2S" | "BLAKE2S256" => Self::Blake2s,
+            "BLAKE3" => Self::Blake3,
+            "CRC32" => Self::Crc32,
+            "CRC32C" => Self::Crc32c,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum CsType {
     MD5,
     Sha1,
@@ -80,4 +212,149 @@ pub enum CsType {
     Sha256,
     Sha384,
     Sha512,
+    Sha3_256,
+    Sha3_512,
+    /// BLAKE2b with a 512 bit output
+    Blake2b,
+    /// BLAKE2s with a 256 bit output
+    Blake2s,
+    Blake3,
+    /// CRC-32 as used by zip and gzip
+    Crc32,
+    /// CRC-32C (Castagnoli)
+    Crc32c,
+}
+
+/// Decode a digest written as hex in either case, or as base64. Hex is preferred when the text is valid as both,
+/// unless its length doesn't match the expected length of the digest.
+fn decode(hash: &str, length: Option<usize>) -> Option<Vec<u8>> {
+    let hash = hash.trim();
+    let is_hex = hash.len().is_multiple_of(2) && hash.bytes().all(|byte| byte.is_ascii_hexdigit());
+    if is_hex && length.is_none_or(|length| hash.len() == length * 2) {
+        let digit = |byte: u8| (byte as char).to_digit(16).unwrap() as u8;
+        return Some(
+            hash.as_bytes()
+                .chunks(2)
+                .map(|pair| digit(pair[0]) << 4 | digit(pair[1]))
+                .collect(),
+        );
+    }
+    BASE64_ENGINES.iter().find_map(|engine| engine.decode(hash).ok())
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+/// Digests of the same data, shared by the tests of everything which reads checksums
+#[cfg(test)]
+pub(crate) mod fixture {
+    use super::Checksum;
+
+    pub(crate) const DATA: &[u8] = b"hello\n";
+    pub(crate) const MD5: &str = "b1946ac92492d2347c6235b4d2611184";
+    pub(crate) const SHA1: &str = "f572d396fae9206628714fb2ce00f72e94f2258f";
+    pub(crate) const SHA256: &str = "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+    pub(crate) const SHA256_BASE64: &str = "WJG1tSLV3whtD/CxEPvZ0hu0/HFjrzTQgoai6Eb2vgM=";
+    pub(crate) const SHA512: &str = "e7c22b994c59d9cf2b48e549b1e24666636045930d3da7c1acb299d1c3b7f931f94aae41edda2c2b207a36e10f8bcb8d45223e54878f5b316e7ce3b6bc019629";
+    pub(crate) const BLAKE2B: &str = "f60ce482e5cc1229f39d71313171a8d9f4ca3a87d066bf4b205effb528192a75f14f3271e2c1a90e1de53f275b4d4793eef2f5e31ea90d2ce29d2e481c36435f";
+
+    /// Whether a checksum matches [`DATA`]
+    pub(crate) fn verifies(mut checksum: Checksum) -> bool {
+        checksum.update(DATA);
+        checksum.verify()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fixture::*;
+
+    #[test]
+    fn hex_is_decoded_in_either_case() {
+        let expected = decode(SHA256, None).unwrap();
+        assert_eq!(expected.len(), 32);
+        assert_eq!(hex(&expected), SHA256);
+        assert_eq!(decode(&SHA256.to_uppercase(), Some(32)), Some(expected.clone()));
+        assert_eq!(decode(&format!(" {SHA256}\n"), Some(32)), Some(expected));
+    }
+
+    #[test]
+    fn base64_is_decoded_with_or_without_padding() {
+        let expected = decode(SHA256, None);
+        assert_eq!(decode(SHA256_BASE64, None), expected);
+        assert_eq!(decode(SHA256_BASE64.trim_end_matches('='), None), expected);
+        assert_eq!(decode("WJG1tSLV3whtD_CxEPvZ0hu0_HFjrzTQgoai6Eb2vgM", None), expected);
+        assert_eq!(decode("not a hash", None), None);
+    }
+
+    #[test]
+    fn hex_of_the_wrong_length_is_read_as_base64() {
+        // 32 hex digits are an MD5 hash, but 24 bytes when read as base64
+        assert_eq!(decode(MD5, None).map(|bytes| bytes.len()), Some(16));
+        assert_eq!(decode(MD5, Some(32)).map(|bytes| bytes.len()), Some(24));
+    }
+
+    #[test]
+    fn algorithm_is_guessed_from_the_length() {
+        assert!(verifies(Checksum::new(SHA256).unwrap()));
+        assert!(verifies(Checksum::new(SHA256.to_uppercase()).unwrap()));
+        assert!(verifies(Checksum::new(SHA256_BASE64).unwrap()));
+        assert!(verifies(Checksum::new(MD5).unwrap()));
+        assert!(verifies(Checksum::new("f572d396fae9206628714fb2ce00f72e94f2258f").unwrap()));
+        assert!(!verifies(Checksum::new(SHA256.replace('5', "6")).unwrap()));
+        assert!(matches!(Checksum::new("abcd"), Err(ChecksumError::UnrecognizedSize)));
+        assert!(matches!(Checksum::new("not a hash"), Err(ChecksumError::InvalidEncoding)));
+    }
+
+    #[test]
+    fn sri_strings_are_recognized() {
+        assert!(verifies(Checksum::new(format!("sha256-{SHA256_BASE64}")).unwrap()));
+        assert!(verifies(
+            Checksum::new("sha512-58IrmUxZ2c8rSOVJseJGZmNgRZMNPafBrLKZ0cO3+TH5Sq5B7dosKyB6NuEPi8uNRSI+VIePWzFufOO2vAGWKQ==").unwrap()
+        ));
+        assert!(matches!(
+            Checksum::new("sha256-sZRqySSS0jR8YjW00mERhA=="),
+            Err(ChecksumError::UnrecognizedSize)
+        ));
+    }
+
+    #[test]
+    fn prefixes_name_the_algorithm() {
+        assert!(verifies(Checksum::new(format!("sha256:{SHA256}")).unwrap()));
+        assert!(verifies(Checksum::new(format!("SHA-256:{SHA256_BASE64}")).unwrap()));
+        assert!(verifies(Checksum::new(format!("md5:{MD5}")).unwrap()));
+        assert!(verifies(
+            Checksum::new("sha3-256:sxTihJPq6dq1esTwxtiHvdu-uBDpANgYOVrOVY6WUW0").unwrap()
+        ));
+        assert!(verifies(Checksum::new("crc32:363a3020").unwrap()));
+        assert!(matches!(
+            Checksum::new(format!("sha512:{SHA256}")),
+            Err(ChecksumError::UnrecognizedSize)
+        ));
+        assert!(matches!(
+            Checksum::new(format!("whirlpool:{SHA256}")),
+            Err(ChecksumError::UnrecognizedType)
+        ));
+    }
+
+    #[test]
+    fn explicit_algorithms_are_used() {
+        assert!(verifies(Checksum::new_inner(SHA256, CsType::Sha256)));
+        assert!(!verifies(Checksum::new_inner(SHA256, CsType::Sha3_256)));
+        assert!(!verifies(Checksum::new_inner("not a hash", CsType::Sha256)));
+    }
+
+    #[test]
+    fn every_checksum_must_match() {
+        let sha256 = Checksum::new(SHA256).unwrap();
+        let sha512 = Checksum::new(SHA512).unwrap();
+        let wrong = Checksum::new(SHA512.replace('e', "f")).unwrap();
+        // A tiny buffer makes sure the data is hashed in pieces
+        assert!(verify_reader(&[sha256.clone(), sha512.clone()], DATA, 1).unwrap());
+        assert!(!verify_reader(&[sha256.clone(), wrong.clone()], DATA, 1).unwrap());
+        assert!(!verify_reader(&[wrong, sha256.clone()], DATA, 1).unwrap());
+        assert!(!verify_reader(&[sha256, sha512], &b"hello"[..], 1).unwrap());
+    }
 }
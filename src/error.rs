@@ -7,8 +7,12 @@ pub enum ChecksumError {
     UnrecognizedSize,
     #[error("Unrecognized checksum type")]
     UnrecognizedType,
+    #[error("Checksum is neither hex nor base64")]
+    InvalidEncoding,
     #[error("Input file does not match the given checksum")]
     VerificationFailure,
+    #[error("The checksum file has no entry for {0}")]
+    MissingEntry(String),
 }
 
 #[cfg(feature = "unarchive")]
@@ -18,6 +22,32 @@ pub enum ArchiveError {
     UnarchiveError,
     #[error("Failed to handle archive files: {0}")]
     FileError(#[from] std::io::Error),
+    #[error("Could not recognize the archive format")]
+    UnrecognizedFormat,
+    #[error("Archive entry {0} would be extracted outside of the output directory")]
+    UnsafeEntry(String),
+    #[error("Invalid glob pattern: {0}")]
+    InvalidPattern(#[from] glob::PatternError),
+}
+
+#[cfg(feature = "signatures")]
+#[derive(Debug, Error)]
+pub enum SignatureError {
+    #[error("Invalid public key")]
+    InvalidKey,
+    #[error("Could not parse the signature")]
+    InvalidSignature,
+    #[error("The signature does not match the data or was not made by the trusted key")]
+    VerificationFailure,
+    #[error("A signature for a checksum file was given without a checksum file URL")]
+    MissingChecksumFile,
+    #[error("A signature for a checksum file can't be checked when the download has a checksum of its own")]
+    UnusedChecksumFile,
+    #[cfg(feature = "gpgv")]
+    #[error("Failed to run gpgv: {0}")]
+    Gpgv(std::io::Error),
+    #[error("Failed to handle signature files: {0}")]
+    FileError(#[from] std::io::Error),
 }
 
 #[derive(Debug, Error)]
@@ -32,15 +62,28 @@ pub enum DownloadError {
     ReqwestError(#[from] reqwest::Error),
     #[error("{0}")]
     FileError(#[from] tokio::io::Error),
+    #[error("Download was aborted after another download failed")]
+    Aborted,
+    #[error("Download was cancelled")]
+    Cancelled,
+    #[error("Download was paused")]
+    Paused,
+    #[error("Server responded with a different range than was requested")]
+    RangeMismatch,
     #[error("Invalid amount of threads requested")]
     InvalidThreads,
     #[error("Invalid checksum")]
     InvalidChecksum,
     #[error("Unable to save to file")]
     SaveError,
-    #[cfg(feature = "unarchive")]
-    #[error("File names are unsupported for tarballs or zip archives")]
-    UnsupportedFileName,
+    #[error("Unable to read the saved state of a partial download")]
+    InvalidResumeState,
+    #[cfg(feature = "verification")]
+    #[error("{0}")]
+    ChecksumError(#[from] ChecksumError),
+    #[cfg(feature = "signatures")]
+    #[error("{0}")]
+    SignatureError(#[from] SignatureError),
     #[cfg(feature = "unarchive")]
     #[error("{0}")]
     ArchiveError(#[from] ArchiveError),
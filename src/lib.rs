@@ -1,13 +1,31 @@
 mod downloader;
 mod error;
 
+pub use downloader::cancel::{CancelPolicy, DownloadHandle};
+pub use downloader::collision::CollisionPolicy;
+pub use downloader::events::{DownloadEvent, ProgressObserver};
+pub use downloader::report::{DownloadReport, DownloadSummary, ErrorPolicy};
 pub use downloader::{Download, Downloader};
+pub use error::DownloadError;
 
+#[cfg(feature = "verification")]
+pub use downloader::checksum_file::ChecksumFile;
+#[cfg(feature = "verification")]
+pub use downloader::integrity::{ChecksumSource, ServerChecksums};
 #[cfg(feature = "verification")]
 pub use downloader::verify::{Checksum, CsType};
+#[cfg(feature = "verification")]
+pub use error::ChecksumError;
+
+#[cfg(feature = "signatures")]
+pub use downloader::signature::{PublicKey, Signature};
+#[cfg(feature = "signatures")]
+pub use error::SignatureError;
 
 #[cfg(feature = "render_progress")]
-pub use downloader::Progress;
+pub use downloader::progress::Progress;
 
 #[cfg(feature = "unarchive")]
-pub use downloader::decompress::ArchiveFormat;
+pub use downloader::decompress::{ArchiveFormat, ExtractOptions};
+#[cfg(feature = "unarchive")]
+pub use error::ArchiveError;